@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Back up a file by renaming it aside, following the naming of mv(1).
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rustix::io::Errno;
+
+use crate::{names, Error, ErrorKind, RenameMode, RenameOp};
+
+/// How to name backups, as the `CONTROL` of `--backup` of mv(1).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BackupControl {
+    /// Append a suffix, `~` by default.
+    Simple,
+    /// Append `.~N~` with the next unused number `N`.
+    Numbered,
+    /// Numbered if numbered backups already exist, simple otherwise.
+    #[default]
+    Existing,
+}
+
+/// The error of parsing a [`BackupControl`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid backup type '{}', expect 'none', 'simple', 'numbered' or 'existing'",
+            self.0,
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl BackupControl {
+    /// Parse a `CONTROL` value, including aliases accepted by mv(1). Returns
+    /// `None` for `none` or `off`, which disable backups.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is unknown.
+    pub fn parse(s: &str) -> Result<Option<Self>, ParseError> {
+        Ok(Some(match s {
+            "none" | "off" => return Ok(None),
+            "simple" | "never" => Self::Simple,
+            "numbered" | "t" => Self::Numbered,
+            "existing" | "nil" => Self::Existing,
+            _ => return Err(ParseError(s.to_owned())),
+        }))
+    }
+}
+
+impl FromStr for BackupControl {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)?.ok_or_else(|| ParseError(s.to_owned()))
+    }
+}
+
+/// Rename `path` aside as a backup, and return the path of the backup.
+///
+/// The backup is created by a `RENAME_NOREPLACE` rename in the same directory,
+/// so it never involves copying and never replaces anything. For numbered
+/// backups, the next number is retried until an unused one is found, so
+/// concurrent backups never race. A simple backup fails if the previous one
+/// still exists.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read, or the rename fails.
+pub fn backup(path: &Path, control: BackupControl, suffix: &OsStr) -> Result<PathBuf, Error> {
+    let last = match control {
+        BackupControl::Simple => None,
+        BackupControl::Numbered => Some(last_number(path)?.unwrap_or(0)),
+        BackupControl::Existing => last_number(path)?,
+    };
+
+    let Some(mut n) = last else {
+        let backup = append(path, suffix);
+        RenameOp::new(path, &backup, RenameMode::NoReplace).execute()?;
+        return Ok(backup);
+    };
+    loop {
+        n += 1;
+        let backup = append(path, format!(".~{n}~"));
+        match RenameOp::new(path, &backup, RenameMode::NoReplace).execute() {
+            Ok(()) => return Ok(backup),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn append(path: &Path, suffix: impl AsRef<OsStr>) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+/// Find the largest `N` of existing backups named `path.~N~`.
+fn last_number(path: &Path) -> Result<Option<u64>, Error> {
+    let err = |err| Error::new(err, RenameMode::NoReplace);
+    let base = path
+        .file_name()
+        .ok_or_else(|| err(Errno::INVAL.into()))?
+        .as_bytes();
+    let dir = names::parent(path);
+
+    let mut last = None;
+    for entry in std::fs::read_dir(dir).map_err(err)? {
+        let name = entry.map_err(err)?.file_name();
+        let n = name
+            .as_bytes()
+            .strip_prefix(base)
+            .and_then(|s| s.strip_prefix(b".~"))
+            .and_then(|s| s.strip_suffix(b"~"))
+            .filter(|s| !s.is_empty() && s.iter().all(u8::is_ascii_digit))
+            .and_then(|s| std::str::from_utf8(s).ok()?.parse::<u64>().ok());
+        last = last.max(n);
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use super::{backup, BackupControl};
+    use crate::testutil::ScratchDir;
+    use crate::ErrorKind;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(BackupControl::parse("off"), Ok(None));
+        assert_eq!(BackupControl::parse("t"), Ok(Some(BackupControl::Numbered)));
+        assert_eq!(
+            BackupControl::parse("existing"),
+            Ok(Some(BackupControl::Existing))
+        );
+        assert_eq!(
+            BackupControl::parse("foo").unwrap_err().to_string(),
+            "Invalid backup type 'foo', expect 'none', 'simple', 'numbered' or 'existing'",
+        );
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = ScratchDir::new("backup");
+        let path = dir.join("foo");
+        let suffix = OsStr::new("~");
+
+        fs::write(&path, "1").unwrap();
+        let ret = backup(&path, BackupControl::Existing, suffix).unwrap();
+        assert_eq!(ret, dir.join("foo~"));
+        assert!(!path.exists());
+
+        fs::write(&path, "2").unwrap();
+        let err = backup(&path, BackupControl::Simple, suffix).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        let ret = backup(&path, BackupControl::Numbered, suffix).unwrap();
+        assert_eq!(ret, dir.join("foo.~1~"));
+
+        // Numbers already taken are skipped.
+        fs::write(dir.join("foo.~3~"), "").unwrap();
+        fs::write(dir.join("foobar.~9~"), "").unwrap();
+        fs::write(&path, "3").unwrap();
+        let ret = backup(&path, BackupControl::Existing, suffix).unwrap();
+        assert_eq!(ret, dir.join("foo.~4~"));
+        assert_eq!(fs::read_to_string(&ret).unwrap(), "3");
+    }
+}
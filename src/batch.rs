@@ -0,0 +1,725 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Execute a batch of operations like the `rawmv` command does, including
+//! handling existing destinations, rolling back an atomic batch on failure,
+//! and simulating a dry run.
+//!
+//! Nothing is printed here. Events are passed to an [`Observer`] as they
+//! happen, so that callers can report them in their own way, and outcomes are
+//! counted in a [`Summary`].
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::backup::{self, BackupControl};
+use crate::expect::Expected;
+use crate::journal::Journal;
+use crate::resolve::Resolver;
+use crate::sync::DirSync;
+use crate::unique::{self, NameFormat};
+use crate::update::Update;
+use crate::{names, Error, ErrorKind, RenameMode, RenameOp};
+
+/// The outcome of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The operation succeeded.
+    Renamed,
+    /// The destination exists and is kept, eg. by [`OnExists::Skip`].
+    Skipped,
+    /// The observer refused to overwrite the destination.
+    PromptDeclined,
+    /// The operation failed.
+    Failed,
+    /// The operation succeeded but was reverted later, since another one in
+    /// the same batch failed.
+    RolledBack,
+}
+
+/// Counts of outcomes of all operations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// The number of successful operations.
+    pub renamed: usize,
+    /// The number of skipped operations.
+    pub skipped: usize,
+    /// The number of operations declined by the observer.
+    pub prompt_declined: usize,
+    /// The number of failed operations.
+    pub failed: usize,
+    /// The number of operations failed with [`ErrorKind::CrossDevice`]. They
+    /// are also counted in `failed`.
+    pub cross_device: usize,
+    /// The number of operations rolled back. They are no longer counted in
+    /// `renamed`.
+    pub rolled_back: usize,
+    /// The number of directories which cannot be synced. They are not
+    /// counted as operations.
+    pub sync_failed: usize,
+    /// Whether an operation cannot be recorded in the journal, which stops
+    /// all remaining operations.
+    pub journal_failed: bool,
+}
+
+impl Summary {
+    /// Count an outcome, and the error which caused it, if any. A rolled back
+    /// operation must have been counted as renamed before.
+    pub fn add(&mut self, outcome: Outcome, error: Option<&Error>) {
+        if outcome == Outcome::Failed
+            && error.is_some_and(|err| err.kind() == ErrorKind::CrossDevice)
+        {
+            self.cross_device += 1;
+        }
+        *match outcome {
+            Outcome::Renamed => &mut self.renamed,
+            Outcome::Skipped => &mut self.skipped,
+            Outcome::PromptDeclined => &mut self.prompt_declined,
+            Outcome::Failed => &mut self.failed,
+            Outcome::RolledBack => {
+                self.renamed -= 1;
+                &mut self.rolled_back
+            }
+        } += 1;
+    }
+
+    /// The number of all operations.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.renamed + self.skipped + self.prompt_declined + self.failed + self.rolled_back
+    }
+}
+
+/// The decision about an existing destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Overwrite the destination.
+    Overwrite,
+    /// Keep the destination.
+    Keep,
+    /// Keep the destination and stop processing any further operations.
+    Quit,
+}
+
+/// How to handle an existing destination, which fails a rename without
+/// replacing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum OnExists {
+    /// Fail the operation.
+    #[default]
+    Fail,
+    /// Keep the destination and skip the operation.
+    Skip,
+    /// Ask [`Observer::ask`].
+    Ask,
+    /// Overwrite the destination, after backing it up if requested.
+    Overwrite,
+    /// Rename to an unused name by the format instead.
+    Unique(NameFormat),
+}
+
+/// The predicted handling of an operation by [`Batch::simulate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Prediction {
+    /// Renamed without any conflict.
+    Rename,
+    /// Skipped by [`OnExists::Skip`].
+    Skip,
+    /// Skipped, since the destination is not replaced by [`Batch::update`].
+    SkipByUpdate,
+    /// Decided by [`Observer::ask`], which is assumed to overwrite.
+    Ask,
+    /// Renamed after backing up the destination.
+    BackUp,
+    /// Renamed to an unused name by [`OnExists::Unique`].
+    RenameUnique,
+    /// Renamed over the destination.
+    Replace,
+    /// Failed.
+    Fail,
+}
+
+impl Prediction {
+    /// The outcome if the prediction comes true.
+    #[must_use]
+    pub fn outcome(self) -> Outcome {
+        match self {
+            Self::Skip | Self::SkipByUpdate => Outcome::Skipped,
+            Self::Fail => Outcome::Failed,
+            _ => Outcome::Renamed,
+        }
+    }
+}
+
+/// The report of a single operation, passed to [`Observer::finished`].
+#[derive(Debug)]
+pub struct Report<'a> {
+    /// The operation, with the mode it was finally executed with.
+    pub op: &'a RenameOp,
+    /// The outcome.
+    pub outcome: Outcome,
+    /// The path which the destination was backed up to, if any.
+    pub backup: Option<&'a Path>,
+    /// The error which caused the outcome, if any. It is also set for skipped
+    /// or declined operations, which are caused by an existing destination.
+    pub error: Option<&'a Error>,
+}
+
+/// Receive events of [`Batch::run`], eg. to report them. All methods do
+/// nothing by default, and existing destinations are kept.
+pub trait Observer {
+    /// Decide about the existing destination of an operation for
+    /// [`OnExists::Ask`].
+    fn ask(&mut self, _op: &RenameOp) -> Decision {
+        Decision::Keep
+    }
+
+    /// An existing destination is backed up by the given operation, which is
+    /// recorded in the journal like other operations.
+    fn backed_up(&mut self, _backup: &RenameOp) {}
+
+    /// An operation has finished, or has been rolled back.
+    fn finished(&mut self, _report: &Report<'_>) {}
+
+    /// Nothing of an atomic batch is started, since some operations are
+    /// expected to fail, which have been reported as failed.
+    fn aborted(&mut self) {}
+
+    /// An operation is done but cannot be recorded in the journal. No further
+    /// operations are started.
+    fn record_failed(&mut self, _op: &RenameOp, _err: &io::Error) {}
+
+    /// The backup of the failed operation cannot be moved back while rolling
+    /// back, so nothing else is rolled back.
+    fn restore_failed(&mut self, _backup: &RenameOp, _err: &Error) {}
+
+    /// An operation cannot be reverted while rolling back, so operations
+    /// before it, which may depend on it, are left.
+    fn roll_back_failed(&mut self, _op: &RenameOp, _err: &Error) {}
+
+    /// A parent directory of renamed paths has been synced, or has failed with
+    /// the error.
+    fn synced(&mut self, _dir: &Path, _error: Option<&io::Error>) {}
+}
+
+/// Options to execute a batch of operations.
+#[derive(Debug, Default)]
+pub struct Batch {
+    /// How to handle existing destinations.
+    pub on_exists: OnExists,
+    /// Keep existing destinations which are not to be replaced by this, as
+    /// skipped, before handling them by `on_exists`.
+    pub update: Option<Update>,
+    /// Back up existing destinations before overwriting them, with the
+    /// suffix of simple backups.
+    pub backup: Option<(BackupControl, OsString)>,
+    /// Properties expected of sources, checked right before renaming them.
+    pub expect: HashMap<PathBuf, Expected>,
+    /// Resolve parent directories of sources and destinations respectively
+    /// by these, instead of relative to the current directory.
+    pub dirs: Option<(Resolver, Resolver)>,
+    /// Preflight all operations before starting, and roll back completed
+    /// ones if any fails.
+    pub atomic: bool,
+    /// Sync parent directories of renamed paths after all operations.
+    pub sync: bool,
+}
+
+impl Batch {
+    /// Execute `ops` in order, and handle existing destinations as requested.
+    /// Completed operations, including backups, are recorded in `journal` if
+    /// any. Outcomes are added to `summary`, which may already contain
+    /// failures of earlier steps, eg. locating sources.
+    pub fn run(
+        &self,
+        ops: Vec<RenameOp>,
+        mut journal: Option<&mut Journal>,
+        observer: &mut impl Observer,
+        summary: &mut Summary,
+    ) {
+        if self.atomic && !self.preflight(&ops, observer, summary) {
+            observer.aborted();
+            return;
+        }
+
+        // Completed operations with their backups, if any, to be rolled back.
+        let mut completed = Vec::new();
+        // Renamed paths whose parents are to be synced, with whether they are
+        // sources.
+        let mut to_sync = Vec::new();
+        for mut op in ops {
+            let mode = op.mode;
+            let src = op.src.clone();
+            let mut ret = self.execute(&op);
+            let mut kept = None;
+            let mut quit = false;
+            let mut backup_op = None;
+            let exists = matches!(&ret, Err(err) if err.kind() == ErrorKind::AlreadyExists);
+            if let (true, OnExists::Unique(format)) = (exists, &self.on_exists) {
+                ret =
+                    unique::rename_unique(&op, format, |op| self.execute(op)).map(|done| op = done);
+            } else if exists {
+                let overwrite = self
+                    .should_overwrite(&op, observer, &mut kept, &mut quit)
+                    .unwrap_or_else(|err| {
+                        ret = Err(err);
+                        false
+                    });
+                if !overwrite {
+                    // Report the error below.
+                } else if let Some((control, suffix)) = &self.backup {
+                    // Keep NOREPLACE, so that a destination recreated in
+                    // between is never lost.
+                    ret = backup::backup(&op.dest, *control, suffix).and_then(|backup| {
+                        let done = RenameOp::new(&op.dest, backup, RenameMode::NoReplace);
+                        observer.backed_up(&done);
+                        let recorded = record(journal.as_deref_mut(), &done, observer, summary);
+                        backup_op = Some(done);
+                        recorded.map_err(|err| Error::new(err, mode))?;
+                        self.execute(&op)
+                    });
+                } else {
+                    op.mode = mode.replacing();
+                    ret = self.execute(&op);
+                }
+            }
+
+            let outcome = match (&ret, kept) {
+                (_, Some(outcome)) => outcome,
+                (Ok(()), None) => {
+                    // It is done anyway. Stop after reporting it.
+                    let _ = record(journal.as_deref_mut(), &op, observer, summary);
+                    Outcome::Renamed
+                }
+                (Err(_), None) => Outcome::Failed,
+            };
+            summary.add(outcome, ret.as_ref().err());
+            observer.finished(&Report {
+                op: &op,
+                outcome,
+                backup: backup_op.as_ref().map(|backup| backup.dest.as_path()),
+                error: ret.as_ref().err(),
+            });
+
+            if self.sync && outcome == Outcome::Renamed {
+                // The destination may be changed to a unique name.
+                to_sync.extend([(src, true), (op.dest.clone(), false)]);
+            }
+            if outcome == Outcome::Renamed {
+                completed.push((op, backup_op));
+            } else if outcome == Outcome::Failed && self.atomic {
+                roll_back(backup_op, completed, observer, summary);
+                break;
+            }
+            if summary.journal_failed || quit {
+                break;
+            }
+        }
+
+        self.sync_parents(to_sync, observer, summary);
+    }
+
+    /// Predict the handling of each of `ops` without touching the filesystem,
+    /// assuming that earlier ones are renamed as predicted. The error which a
+    /// prediction other than [`Prediction::Rename`] is based on is returned
+    /// along with it.
+    #[must_use]
+    pub fn simulate(&self, ops: &[RenameOp]) -> Vec<(Prediction, Option<Error>)> {
+        let mut simulation = Simulation::default();
+        let mut ret = Vec::with_capacity(ops.len());
+        for op in ops {
+            let Err(err) = self
+                .check_expected(op)
+                .and_then(|()| simulation.preflight(op))
+            else {
+                simulation.apply(op);
+                ret.push((Prediction::Rename, None));
+                continue;
+            };
+            let prediction = if err.kind() != ErrorKind::AlreadyExists {
+                Prediction::Fail
+            } else if self.on_exists == OnExists::Skip {
+                Prediction::Skip
+            } else if self
+                .update
+                .is_some_and(|update| !update.replaces(&op.src, &op.dest, op.mode).unwrap_or(true))
+            {
+                Prediction::SkipByUpdate
+            } else {
+                match &self.on_exists {
+                    OnExists::Ask => Prediction::Ask,
+                    OnExists::Overwrite if self.backup.is_some() => Prediction::BackUp,
+                    OnExists::Overwrite => Prediction::Replace,
+                    OnExists::Unique(_) => Prediction::RenameUnique,
+                    OnExists::Fail | OnExists::Skip => Prediction::Fail,
+                }
+            };
+            ret.push((prediction, Some(err)));
+        }
+        ret
+    }
+
+    fn check_expected(&self, op: &RenameOp) -> Result<(), Error> {
+        match self.expect.get(&op.src) {
+            Some(expected) => expected.check(&op.src, op.mode),
+            None => Ok(()),
+        }
+    }
+
+    fn execute(&self, op: &RenameOp) -> Result<(), Error> {
+        self.check_expected(op)?;
+        match &self.dirs {
+            Some((src_dir, dest_dir)) => op.execute_with(src_dir, dest_dir),
+            None => op.execute(),
+        }
+    }
+
+    /// Decide whether to overwrite the existing destination of `op`, asking
+    /// `observer` if requested. If it is kept deliberately, `kept` is set to
+    /// the outcome, and `quit` is set if no further operations are to be
+    /// started. Otherwise, not overwriting it means a failure.
+    fn should_overwrite(
+        &self,
+        op: &RenameOp,
+        observer: &mut impl Observer,
+        kept: &mut Option<Outcome>,
+        quit: &mut bool,
+    ) -> Result<bool, Error> {
+        if self.on_exists == OnExists::Skip {
+            *kept = Some(Outcome::Skipped);
+            return Ok(false);
+        }
+        // The destination may still be changed after this check. See
+        // `crate::update`.
+        if let Some(update) = self.update {
+            if !update.replaces(&op.src, &op.dest, op.mode)? {
+                *kept = Some(Outcome::Skipped);
+                return Ok(false);
+            }
+        }
+        match self.on_exists {
+            OnExists::Ask => {
+                let decision = observer.ask(op);
+                if decision != Decision::Overwrite {
+                    *kept = Some(Outcome::PromptDeclined);
+                }
+                *quit = decision == Decision::Quit;
+                Ok(decision == Decision::Overwrite)
+            }
+            OnExists::Overwrite => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Preflight all operations of an atomic batch, and report failures. Return
+    /// whether all of them are expected to succeed.
+    fn preflight(
+        &self,
+        ops: &[RenameOp],
+        observer: &mut impl Observer,
+        summary: &mut Summary,
+    ) -> bool {
+        let mut simulation = Simulation::default();
+        for op in ops {
+            let ret = match self
+                .check_expected(op)
+                .and_then(|()| simulation.preflight(op))
+            {
+                // Backed up before being overwritten.
+                Err(err)
+                    if err.kind() == ErrorKind::AlreadyExists
+                        && self.on_exists == OnExists::Overwrite
+                        && self.backup.is_some() =>
+                {
+                    Ok(())
+                }
+                ret => ret,
+            };
+            match ret {
+                Ok(()) => simulation.apply(op),
+                Err(err) => {
+                    summary.add(Outcome::Failed, Some(&err));
+                    observer.finished(&Report {
+                        op,
+                        outcome: Outcome::Failed,
+                        backup: None,
+                        error: Some(&err),
+                    });
+                }
+            }
+        }
+        summary.failed == 0
+    }
+
+    /// Sync parent directories of `paths`, each with whether it is a source.
+    /// Paths in the same directory are only tried once.
+    fn sync_parents(
+        &self,
+        paths: Vec<(PathBuf, bool)>,
+        observer: &mut impl Observer,
+        summary: &mut Summary,
+    ) {
+        let mut dir_sync = DirSync::new();
+        let mut seen = HashSet::new();
+        for (path, is_src) in paths {
+            let dir = names::parent(&path);
+            if !seen.insert((dir.to_owned(), is_src)) {
+                continue;
+            }
+            let resolver = self
+                .dirs
+                .as_ref()
+                .map(|(src_dir, dest_dir)| if is_src { src_dir } else { dest_dir });
+            let ret = dir_sync.sync_parent(&path, resolver);
+            if ret.is_err() {
+                summary.sync_failed += 1;
+            }
+            observer.synced(dir, ret.as_ref().err());
+        }
+    }
+}
+
+/// Record a completed operation in `journal`, if any. A failure is passed to
+/// `observer` and marked in `summary`, and the caller must stop, so that
+/// nothing is moved without being recorded.
+fn record(
+    journal: Option<&mut Journal>,
+    op: &RenameOp,
+    observer: &mut impl Observer,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let Some(journal) = journal else {
+        return Ok(());
+    };
+    journal.record(op).inspect_err(|err| {
+        observer.record_failed(op, err);
+        summary.journal_failed = true;
+    })
+}
+
+/// Restore the backup of the failed operation, if any, and revert completed
+/// operations in reverse order. Backups are moved back after the operation
+/// which replaced them.
+fn roll_back(
+    failed_backup: Option<RenameOp>,
+    completed: Vec<(RenameOp, Option<RenameOp>)>,
+    observer: &mut impl Observer,
+    summary: &mut Summary,
+) {
+    // The destination may be backed up but not replaced.
+    if let Some(backup) = failed_backup {
+        if let Err(err) = backup.revert() {
+            observer.restore_failed(&backup, &err);
+            return;
+        }
+    }
+    for (op, backup) in completed.into_iter().rev() {
+        let ret = op
+            .revert()
+            .and_then(|()| backup.as_ref().map_or(Ok(()), RenameOp::revert));
+        if let Err(err) = ret {
+            // Leave the rest, which may depend on this one.
+            observer.roll_back_failed(&op, &err);
+            return;
+        }
+        summary.add(Outcome::RolledBack, None);
+        observer.finished(&Report {
+            op: &op,
+            outcome: Outcome::RolledBack,
+            backup: backup.as_ref().map(|backup| backup.dest.as_path()),
+            error: None,
+        });
+    }
+}
+
+/// Preflight a sequence of operations without touching the filesystem, by
+/// tracking paths which earlier operations would create or remove.
+#[derive(Debug, Default)]
+pub struct Simulation {
+    /// Simulated existence of paths touched by previous operations.
+    overlay: HashMap<PathBuf, bool>,
+}
+
+impl Simulation {
+    /// Same as [`RenameOp::preflight`], but assume that the operations
+    /// applied before are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error which `op` is expected to fail with.
+    pub fn preflight(&self, op: &RenameOp) -> Result<(), Error> {
+        op.preflight_with(|path| match self.overlay.get(path) {
+            Some(&exists) => Ok(exists),
+            None => match path.symlink_metadata() {
+                Ok(_) => Ok(true),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(err) => Err(err),
+            },
+        })
+    }
+
+    /// Assume that `op` is done.
+    pub fn apply(&mut self, op: &RenameOp) {
+        if op.mode != RenameMode::Exchange {
+            let whiteout = matches!(op.mode, RenameMode::Whiteout { .. });
+            self.overlay.insert(op.src.clone(), whiteout);
+            self.overlay.insert(op.dest.clone(), true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Batch, Decision, Observer, OnExists, Outcome, Prediction, Report, Summary};
+    use crate::backup::BackupControl;
+    use crate::testutil::ScratchDir;
+    use crate::{ErrorKind, RenameMode, RenameOp};
+
+    /// Answer with `decisions` in order, and remember outcomes.
+    #[derive(Default)]
+    struct Recorder {
+        decisions: Vec<Decision>,
+        outcomes: Vec<Outcome>,
+        aborted: bool,
+    }
+
+    impl Observer for Recorder {
+        fn ask(&mut self, _op: &RenameOp) -> Decision {
+            self.decisions.remove(0)
+        }
+
+        fn finished(&mut self, report: &Report<'_>) {
+            self.outcomes.push(report.outcome);
+        }
+
+        fn aborted(&mut self) {
+            self.aborted = true;
+        }
+    }
+
+    #[test]
+    fn test_run_backup() {
+        let dir = ScratchDir::new("batch-backup");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        for atomic in [false, true] {
+            fs::write(&a, "a").unwrap();
+            fs::write(&b, "b").unwrap();
+            let batch = Batch {
+                on_exists: OnExists::Overwrite,
+                backup: Some((BackupControl::Simple, ".bak".into())),
+                atomic,
+                ..Batch::default()
+            };
+            let ops = vec![RenameOp::new(&a, &b, RenameMode::NoReplace)];
+            let mut summary = Summary::default();
+            batch.run(ops, None, &mut Recorder::default(), &mut summary);
+            assert_eq!(summary.renamed, 1, "{atomic}");
+            assert!(!a.exists());
+            assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+            assert_eq!(fs::read_to_string(dir.join("b.bak")).unwrap(), "b");
+            fs::remove_file(dir.join("b.bak")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_run_ask() {
+        let dir = ScratchDir::new("batch-ask");
+        let names = ["a", "b", "c", "d"].map(|name| dir.join(name));
+        for name in &names {
+            fs::write(name, name.to_str().unwrap()).unwrap();
+        }
+        let ops = names
+            .windows(2)
+            .map(|pair| RenameOp::new(&pair[0], &pair[1], RenameMode::NoReplace))
+            .collect();
+        let batch = Batch {
+            on_exists: OnExists::Ask,
+            ..Batch::default()
+        };
+        let mut recorder = Recorder {
+            decisions: vec![Decision::Keep, Decision::Quit],
+            ..Recorder::default()
+        };
+        let mut summary = Summary::default();
+        batch.run(ops, None, &mut recorder, &mut summary);
+        // Nothing is started after quitting.
+        assert_eq!(recorder.outcomes, [Outcome::PromptDeclined; 2]);
+        assert_eq!(summary.prompt_declined, 2);
+        assert_eq!(summary.total(), 2);
+        assert!(recorder.decisions.is_empty());
+        assert!(names.iter().all(|name| name.exists()));
+    }
+
+    #[test]
+    fn test_run_atomic() {
+        let dir = ScratchDir::new("batch-atomic");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "").unwrap();
+        let ops = vec![
+            RenameOp::new(&a, &b, RenameMode::NoReplace),
+            RenameOp::new(dir.join("missing"), dir.join("c"), RenameMode::NoReplace),
+        ];
+        let batch = Batch {
+            atomic: true,
+            ..Batch::default()
+        };
+        let mut recorder = Recorder::default();
+        let mut summary = Summary::default();
+        batch.run(ops, None, &mut recorder, &mut summary);
+        assert!(recorder.aborted);
+        assert_eq!(recorder.outcomes, [Outcome::Failed]);
+        assert_eq!(summary.renamed, 0);
+        assert!(a.exists());
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_simulate() {
+        let dir = ScratchDir::new("batch-simulate");
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+        fs::write(&a, "").unwrap();
+        fs::write(&c, "").unwrap();
+        // `b` only exists after the first one.
+        let ops = [
+            RenameOp::new(&a, &b, RenameMode::NoReplace),
+            RenameOp::new(&c, &b, RenameMode::NoReplace),
+            RenameOp::new(&a, &c, RenameMode::NoReplace),
+        ];
+        let predict = |on_exists, backup| {
+            let batch = Batch {
+                on_exists,
+                backup,
+                ..Batch::default()
+            };
+            let predictions = batch.simulate(&ops);
+            assert_eq!(predictions[0].0, Prediction::Rename);
+            assert!(predictions[0].1.is_none());
+            assert_eq!(
+                predictions[1].1.as_ref().map(crate::Error::kind),
+                Some(ErrorKind::AlreadyExists),
+            );
+            assert_eq!(predictions[2].0, Prediction::Fail);
+            predictions[1].0
+        };
+        assert_eq!(predict(OnExists::Fail, None), Prediction::Fail);
+        assert_eq!(predict(OnExists::Skip, None), Prediction::Skip);
+        assert_eq!(predict(OnExists::Ask, None), Prediction::Ask);
+        assert_eq!(predict(OnExists::Overwrite, None), Prediction::Replace);
+        assert_eq!(
+            predict(
+                OnExists::Overwrite,
+                Some((BackupControl::Simple, "~".into()))
+            ),
+            Prediction::BackUp,
+        );
+        assert_eq!(
+            predict(OnExists::Unique("{name}.{n}".parse().unwrap()), None),
+            Prediction::RenameUnique,
+        );
+        assert_eq!(Prediction::SkipByUpdate.outcome(), Outcome::Skipped);
+        assert_eq!(Prediction::Ask.outcome(), Outcome::Renamed);
+        // Nothing is touched.
+        assert!(a.exists() && !b.exists() && c.exists());
+    }
+}
@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Parts of the command line interface which are not useful to other tools
+//! linking against the library, eg. interacting with the user or checking
+//! options.
+pub mod edit;
+pub mod prompt;
+pub mod report;
+pub mod rules;
@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Rename paths by editing a list of their names in a text editor, like
+//! vidir(1).
+//!
+//! Names are written one per line. After the editor exits, each changed line
+//! becomes a rename from the original path to the edited one.
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Expand directories in `paths` to their entries, sorted by name. The current
+/// directory is used if `paths` is empty.
+///
+/// # Errors
+///
+/// Returns an error if any directory cannot be read.
+pub fn list_paths(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let cur_dir = [PathBuf::from(".")];
+    let paths = if paths.is_empty() { &cur_dir } else { paths };
+
+    let mut ret = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            ret.push(path.clone());
+            continue;
+        }
+        let mut names = fs::read_dir(path)?
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        let prefix = if path == Path::new(".") {
+            Path::new("")
+        } else {
+            path
+        };
+        ret.extend(names.into_iter().map(|name| prefix.join(name)));
+    }
+    Ok(ret)
+}
+
+/// Write `paths` into a temporary file, let the user edit it with `editor`, and
+/// return renames of changed lines as `(src, dest)` pairs.
+///
+/// `editor` is run by the shell with the file path appended, so it may contain
+/// arguments.
+///
+/// # Errors
+///
+/// Returns an error if any path contains a newline, the editor fails, or the
+/// edited list is invalid.
+pub fn edit(paths: &[PathBuf], editor: &str) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut content = Vec::new();
+    for path in paths {
+        let bytes = path.as_os_str().as_bytes();
+        if bytes.contains(&b'\n') {
+            return Err(invalid(format!(
+                "Cannot edit names containing newlines: {}",
+                path.display()
+            )));
+        }
+        content.extend_from_slice(bytes);
+        content.push(b'\n');
+    }
+
+    let (tmp_path, mut file) = create_temp_file()?;
+    let ret = (|| {
+        file.write_all(&content)?;
+        drop(file);
+
+        let status = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$1\""))
+            .arg("sh")
+            .arg(&tmp_path)
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("Editor exited with {status}")));
+        }
+
+        diff(paths, &fs::read(&tmp_path)?)
+    })();
+    let _ = fs::remove_file(&tmp_path);
+    ret
+}
+
+/// Compare the original `paths` with the edited list, and return renames of
+/// changed lines.
+///
+/// # Errors
+///
+/// Returns an error if the number of lines is changed, any line is empty, or
+/// multiple lines are the same, so that a path would be clobbered by another
+/// one renamed to it.
+pub fn diff(paths: &[PathBuf], edited: &[u8]) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let edited = edited.strip_suffix(b"\n").unwrap_or(edited);
+    let lines = if edited.is_empty() && paths.is_empty() {
+        Vec::new()
+    } else {
+        edited.split(|&b| b == b'\n').collect()
+    };
+    if lines.len() != paths.len() {
+        return Err(invalid(format!(
+            "Expect {} lines but got {}. Lines must not be added or removed",
+            paths.len(),
+            lines.len(),
+        )));
+    }
+
+    let mut ret = Vec::new();
+    let mut dests = HashMap::new();
+    for (lineno, (src, line)) in paths.iter().zip(lines).enumerate() {
+        if line.is_empty() {
+            return Err(invalid(format!("Line {} is empty", lineno + 1)));
+        }
+        // Unchanged lines count too, since their paths stay.
+        if let Some(prev) = dests.insert(line, src) {
+            return Err(invalid(format!(
+                "Both {} and {} would be renamed to {}",
+                prev.display(),
+                src.display(),
+                Path::new(OsStr::from_bytes(line)).display(),
+            )));
+        }
+        if line != src.as_os_str().as_bytes() {
+            ret.push((src.clone(), OsString::from_vec(line.to_vec()).into()));
+        }
+    }
+    Ok(ret)
+}
+
+fn create_temp_file() -> io::Result<(PathBuf, fs::File)> {
+    let dir = std::env::temp_dir();
+    for i in 0.. {
+        let path = dir.join(format!("rawmv-edit-{}-{i}", std::process::id()));
+        // The names may be private, and the directory is shared.
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{diff, edit, list_paths};
+    use crate::testutil::ScratchDir;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let orig = paths(&["a", "d/b", "c"]);
+        assert_eq!(diff(&orig, b"a\nd/b\nc\n").unwrap(), []);
+        assert_eq!(
+            diff(&orig, b"a\nd/x\ny").unwrap(),
+            [
+                (PathBuf::from("d/b"), PathBuf::from("d/x")),
+                (PathBuf::from("c"), PathBuf::from("y")),
+            ],
+        );
+        assert_eq!(
+            diff(&orig, b"a\nc\n").unwrap_err().to_string(),
+            "Expect 3 lines but got 2. Lines must not be added or removed",
+        );
+        assert_eq!(
+            diff(&orig, b"a\n\nc\n").unwrap_err().to_string(),
+            "Line 2 is empty",
+        );
+        assert_eq!(
+            diff(&orig, b"x\nd/b\nx\n").unwrap_err().to_string(),
+            "Both a and c would be renamed to x",
+        );
+        assert_eq!(
+            diff(&orig, b"c\nd/b\nc\n").unwrap_err().to_string(),
+            "Both a and c would be renamed to c",
+        );
+        // Swapping names is fine.
+        assert_eq!(
+            diff(&orig, b"c\nd/b\na\n").unwrap(),
+            [
+                (PathBuf::from("a"), PathBuf::from("c")),
+                (PathBuf::from("c"), PathBuf::from("a")),
+            ],
+        );
+        assert_eq!(diff(&[], b"").unwrap(), []);
+    }
+
+    #[test]
+    fn test_edit() {
+        let dir = ScratchDir::new("edit");
+        for name in ["b", "a"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let listed = list_paths(&[dir.to_path_buf(), "foo".into()]).unwrap();
+        assert_eq!(listed, [dir.join("a"), dir.join("b"), "foo".into()]);
+
+        let renames = edit(&listed, "sed -i -e 's/foo/bar/'").unwrap();
+        assert_eq!(renames, [(PathBuf::from("foo"), PathBuf::from("bar"))]);
+
+        assert_eq!(
+            edit(&listed, "false").unwrap_err().to_string(),
+            "Editor exited with exit status: 1",
+        );
+        assert!(edit(&["a\nb".into()], "true").is_err());
+        // The temporary file is private.
+        edit(&listed, r#"f() { test "$(stat -c %a "$1")" = 600; }; f"#).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Ask the user whether to overwrite existing destinations.
+//!
+//! Answers are read from `/dev/tty`, so prompting still works when stdin is
+//! used for other purposes, eg. `--from-file -`. Stdin is only used if there
+//! is no controlling terminal.
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use rawmv::batch::Decision;
+use rustix::fs::{statx, AtFlags, FileType, StatxFlags, CWD};
+
+const HELP: &str = "\
+y: overwrite this destination
+n: keep this destination (default)
+a: overwrite this and all later destinations
+N: keep this and all later destinations
+q: keep this destination and quit
+d: show details of both paths";
+
+/// Ask questions about overwriting and remember answers for all later ones.
+pub struct Prompt {
+    input: Box<dyn BufRead>,
+    remembered: Option<Decision>,
+}
+
+impl std::fmt::Debug for Prompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Prompt")
+            .field("remembered", &self.remembered)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Prompt {
+    /// Read answers from `/dev/tty`, or stdin if it cannot be opened.
+    #[must_use]
+    pub fn new() -> Self {
+        match File::open("/dev/tty") {
+            Ok(tty) => Self::with_input(Box::new(BufReader::new(tty))),
+            Err(_) => Self::with_input(Box::new(io::stdin().lock())),
+        }
+    }
+
+    /// Read answers from `input`.
+    #[must_use]
+    pub fn with_input(input: Box<dyn BufRead>) -> Self {
+        Self {
+            input,
+            remembered: None,
+        }
+    }
+
+    /// Ask `question` about overwriting `dest` with `src`, unless an answer
+    /// for all destinations, including quitting, is given before. The question
+    /// is printed to stderr. Destinations are kept if the input ends or cannot
+    /// be read.
+    pub fn ask(&mut self, question: &str, src: &Path, dest: &Path) -> Decision {
+        if let Some(decision) = self.remembered {
+            return decision;
+        }
+        loop {
+            eprint!("rawmv: {question} [y/n/a/N/q/d] ");
+            let _ = io::stderr().flush();
+            let mut input = String::new();
+            if !matches!(self.input.read_line(&mut input), Ok(n) if n != 0) {
+                eprintln!();
+                return Decision::Keep;
+            }
+            match input.trim() {
+                "y" | "yes" => return Decision::Overwrite,
+                "" | "n" | "no" => return Decision::Keep,
+                "a" | "all" => {
+                    self.remembered = Some(Decision::Overwrite);
+                    return Decision::Overwrite;
+                }
+                "N" | "none" => {
+                    self.remembered = Some(Decision::Keep);
+                    return Decision::Keep;
+                }
+                "q" | "quit" => {
+                    self.remembered = Some(Decision::Quit);
+                    return Decision::Quit;
+                }
+                "d" | "details" => {
+                    for (name, path) in [("source", src), ("destination", dest)] {
+                        match describe(path) {
+                            Ok(description) => eprintln!("rawmv:   {name}: {description}"),
+                            Err(err) => eprintln!("rawmv:   {name}: {err}"),
+                        }
+                    }
+                }
+                _ => {
+                    for line in HELP.lines() {
+                        eprintln!("rawmv:   {line}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Describe the type, size, modification time and owner of `path` by
+/// statx(2), without following symlinks.
+///
+/// # Errors
+///
+/// Returns an error if `path` cannot be inspected.
+pub fn describe(path: &Path) -> io::Result<String> {
+    let st = statx(
+        CWD,
+        path,
+        AtFlags::SYMLINK_NOFOLLOW,
+        StatxFlags::TYPE | StatxFlags::SIZE | StatxFlags::MTIME | StatxFlags::UID | StatxFlags::GID,
+    )?;
+    let file_type = match FileType::from_raw_mode(st.stx_mode.into()) {
+        FileType::RegularFile => "regular file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symlink",
+        FileType::Fifo => "fifo",
+        FileType::Socket => "socket",
+        FileType::CharacterDevice => "character device",
+        FileType::BlockDevice => "block device",
+        FileType::Unknown => "unknown",
+    };
+    let mut desc = format!("{file_type}, {} bytes, modified ", st.stx_size);
+    write_time(&mut desc, st.stx_mtime.tv_sec, st.stx_mtime.tv_nsec);
+    write!(desc, ", owned by {}:{}", st.stx_uid, st.stx_gid).unwrap();
+    Ok(desc)
+}
+
+/// Write a Unix timestamp as UTC date and time with nanoseconds.
+fn write_time(out: &mut String, secs: i64, nsecs: u32) {
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Convert days since the epoch to the civil date. See:
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    write!(
+        out,
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{nsecs:09} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use rawmv::batch::Decision;
+
+    use super::Prompt;
+    use crate::testutil::ScratchDir;
+
+    #[test]
+    fn test_ask() {
+        let (src, dest) = (Path::new("a"), Path::new("b"));
+        let mut prompt = Prompt::with_input(Box::new(Cursor::new("d\nx\ny\n\nq\n")));
+        assert_eq!(prompt.ask("?", src, dest), Decision::Overwrite);
+        assert_eq!(prompt.ask("?", src, dest), Decision::Keep);
+        assert_eq!(prompt.ask("?", src, dest), Decision::Quit);
+        assert_eq!(prompt.ask("?", src, dest), Decision::Quit);
+        let mut prompt = Prompt::with_input(Box::new(Cursor::new("")));
+        assert_eq!(prompt.ask("?", src, dest), Decision::Keep);
+
+        let mut prompt = Prompt::with_input(Box::new(Cursor::new("a\n")));
+        assert_eq!(prompt.ask("?", src, dest), Decision::Overwrite);
+        assert_eq!(prompt.ask("?", src, dest), Decision::Overwrite);
+        let mut prompt = Prompt::with_input(Box::new(Cursor::new("N\ny\n")));
+        assert_eq!(prompt.ask("?", src, dest), Decision::Keep);
+        assert_eq!(prompt.ask("?", src, dest), Decision::Keep);
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut out = String::new();
+        super::write_time(&mut out, 951_782_400, 5);
+        assert_eq!(out, "2000-02-29 00:00:00.000000005 UTC");
+        let mut out = String::new();
+        super::write_time(&mut out, -1, 0);
+        assert_eq!(out, "1969-12-31 23:59:59.000000000 UTC");
+
+        let dir = ScratchDir::new("prompt");
+        fs::write(dir.join("foo"), "hello").unwrap();
+        let desc = super::describe(&dir.join("foo")).unwrap();
+        assert!(
+            desc.starts_with("regular file, 5 bytes, modified "),
+            "{desc}"
+        );
+        assert!(super::describe(&dir).unwrap().starts_with("directory, "));
+        assert!(super::describe(&dir.join("missing")).is_err());
+    }
+}
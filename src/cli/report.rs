@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Machine-readable reports of operations, as one JSON object per line.
+//!
+//! Paths are emitted as strings, with invalid UTF-8 replaced by U+FFFD. For
+//! such paths, the exact bytes are additionally emitted in hex as the field
+//! with a `_hex` suffix, eg. `src_hex`, so names are never lost.
+use std::fmt::{self, Write};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use rawmv::batch::{Outcome, Report, Summary};
+use rawmv::ErrorKind;
+
+/// The report of a single operation. It is formatted as a JSON object by
+/// [`Display`](fmt::Display).
+#[derive(Debug)]
+pub struct Entry<'a>(pub &'a Report<'a>);
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.0;
+        f.write_str("{\"type\":\"operation\"")?;
+        write_path(f, "src", &report.op.src)?;
+        write_path(f, "dest", &report.op.dest)?;
+        f.write_str(",\"flags\":[")?;
+        for (i, name) in report.op.mode.flag_names().iter().enumerate() {
+            if i != 0 {
+                f.write_char(',')?;
+            }
+            write_str(f, name)?;
+        }
+        f.write_str("],\"outcome\":")?;
+        write_str(f, outcome_name(report.outcome))?;
+        if let Some(backup) = report.backup {
+            write_path(f, "backup", backup)?;
+        }
+        if let Some(err) = report.error {
+            if let Some(errno) = err.raw_os_error() {
+                write!(f, ",\"errno\":{errno}")?;
+            }
+            f.write_str(",\"error_kind\":")?;
+            write_str(f, error_kind_name(err.kind()))?;
+            f.write_str(",\"error\":")?;
+            write_str(f, &err.to_string())?;
+        }
+        f.write_char('}')
+    }
+}
+
+/// The report of syncing a directory. It is formatted as a JSON object by
+/// [`Display`](fmt::Display).
+#[derive(Debug)]
+pub struct SyncEntry<'a> {
+    /// The directory, or a path inside it.
+    pub dir: &'a Path,
+    /// The error if the directory cannot be synced.
+    pub error: Option<&'a io::Error>,
+}
+
+impl fmt::Display for SyncEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{\"type\":\"sync\"")?;
+        write_path(f, "dir", self.dir)?;
+        f.write_str(",\"outcome\":")?;
+        match self.error {
+            None => write_str(f, "synced")?,
+            Some(err) => {
+                write_str(f, "failed")?;
+                if let Some(errno) = err.raw_os_error() {
+                    write!(f, ",\"errno\":{errno}")?;
+                }
+                f.write_str(",\"error\":")?;
+                write_str(f, &err.to_string())?;
+            }
+        }
+        f.write_char('}')
+    }
+}
+
+/// Counts of outcomes of all operations. It is formatted as a JSON object by
+/// [`Display`](fmt::Display).
+#[derive(Debug)]
+pub struct SummaryEntry<'a>(pub &'a Summary);
+
+impl fmt::Display for SummaryEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let summary = self.0;
+        write!(
+            f,
+            "{{\"type\":\"summary\",\"total\":{},\"renamed\":{},\"skipped\":{},\"prompt_declined\":{},\"failed\":{},\"cross_device\":{},\"rolled_back\":{},\"sync_failed\":{},\"journal_failed\":{}}}",
+            summary.total(),
+            summary.renamed,
+            summary.skipped,
+            summary.prompt_declined,
+            summary.failed,
+            summary.cross_device,
+            summary.rolled_back,
+            summary.sync_failed,
+            summary.journal_failed,
+        )
+    }
+}
+
+fn outcome_name(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Renamed => "renamed",
+        Outcome::Skipped => "skipped",
+        Outcome::PromptDeclined => "prompt-declined",
+        Outcome::Failed => "failed",
+        Outcome::RolledBack => "rolled-back",
+    }
+}
+
+fn error_kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::AlreadyExists => "already-exists",
+        ErrorKind::NotFound => "not-found",
+        ErrorKind::CrossDevice => "cross-device",
+        ErrorKind::PermissionDenied => "permission-denied",
+        ErrorKind::Restricted => "restricted",
+        ErrorKind::Changed => "changed",
+        ErrorKind::Unsupported => "unsupported",
+        _ => "other",
+    }
+}
+
+fn write_path(f: &mut fmt::Formatter<'_>, key: &str, path: &Path) -> fmt::Result {
+    let bytes = path.as_os_str().as_bytes();
+    write!(f, ",\"{key}\":")?;
+    write_str(f, &String::from_utf8_lossy(bytes))?;
+    if std::str::from_utf8(bytes).is_err() {
+        write!(f, ",\"{key}_hex\":\"")?;
+        for b in bytes {
+            write!(f, "{b:02x}")?;
+        }
+        f.write_char('"')?;
+    }
+    Ok(())
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' || c == '\u{7F}' => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use rustix::io::Errno;
+
+    use super::{Entry, SummaryEntry, SyncEntry};
+    use rawmv::batch::{Outcome, Report, Summary};
+    use rawmv::{Error, RenameMode, RenameOp};
+
+    #[test]
+    fn test_entry() {
+        let op = RenameOp::new("a\"\n", "b", RenameMode::NoReplace);
+        let report = Report {
+            op: &op,
+            outcome: Outcome::Renamed,
+            backup: None,
+            error: None,
+        };
+        assert_eq!(
+            Entry(&report).to_string(),
+            r#"{"type":"operation","src":"a\"\n","dest":"b","flags":["RENAME_NOREPLACE"],"outcome":"renamed"}"#,
+        );
+
+        let op = RenameOp::new(OsStr::from_bytes(b"\xFF\x01"), "b", RenameMode::Replace);
+        let err = Error::new(Errno::XDEV.into(), op.mode);
+        let report = Report {
+            op: &op,
+            outcome: Outcome::Failed,
+            backup: Some("b~".as_ref()),
+            error: Some(&err),
+        };
+        assert_eq!(
+            Entry(&report).to_string(),
+            concat!(
+                r#"{"type":"operation","src":"�\u0001","src_hex":"ff01","dest":"b","flags":[],"#,
+                r#""outcome":"failed","backup":"b~","errno":18,"error_kind":"cross-device","#,
+                r#""error":"Invalid cross-device link (os error 18)"}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_sync_entry() {
+        let entry = SyncEntry {
+            dir: "a/b".as_ref(),
+            error: None,
+        };
+        assert_eq!(
+            entry.to_string(),
+            r#"{"type":"sync","dir":"a/b","outcome":"synced"}"#,
+        );
+        let err = Errno::IO.into();
+        let entry = SyncEntry {
+            dir: "a".as_ref(),
+            error: Some(&err),
+        };
+        assert_eq!(
+            entry.to_string(),
+            r#"{"type":"sync","dir":"a","outcome":"failed","errno":5,"error":"Input/output error (os error 5)"}"#,
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = Summary::default();
+        let exist = Error::new(Errno::EXIST.into(), RenameMode::NoReplace);
+        let xdev = Error::new(Errno::XDEV.into(), RenameMode::NoReplace);
+        summary.add(Outcome::Renamed, None);
+        summary.add(Outcome::Renamed, None);
+        summary.add(Outcome::PromptDeclined, Some(&exist));
+        summary.add(Outcome::Failed, Some(&exist));
+        summary.add(Outcome::Failed, Some(&xdev));
+        summary.add(Outcome::RolledBack, None);
+        assert_eq!(
+            SummaryEntry(&summary).to_string(),
+            concat!(
+                r#"{"type":"summary","total":5,"renamed":1,"skipped":0,"prompt_declined":1,"#,
+                r#""failed":2,"cross_device":1,"rolled_back":1,"sync_failed":0,"journal_failed":false}"#,
+            ),
+        );
+    }
+}
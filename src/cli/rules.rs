@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Rules of combining command line options, checked against the names of all
+//! given options, so that conflicts are reported the same way everywhere.
+use anyhow::{bail, ensure, Result};
+
+/// How an option may be combined with others, checked by
+/// [`check_option_rules`].
+enum OptionRule {
+    /// It cannot be used together with any of these options.
+    Excludes(&'static [&'static str]),
+    /// It cannot be used together with any of these options, which are
+    /// replaced by another option when used with it.
+    Replaces(&'static [&'static str], &'static str),
+    /// It can only be used together with this option.
+    Requires(&'static str),
+    /// Only these options can be used together with it.
+    Only(&'static [&'static str]),
+}
+
+/// Options which conflict with sources being checked before renaming.
+const EXPECTING_EXCLUDES: OptionRule = OptionRule::Excludes(&[
+    "--undo",
+    "--replace-dir",
+    "--symlink",
+    "--exchange",
+    "--src-dir",
+    "--reorder",
+    "--edit",
+    "--write-atomic",
+    "--rename",
+]);
+
+/// Options which conflict with renaming relative to opened parents.
+const RESOLVING_EXCLUDES: OptionRule = OptionRule::Excludes(&[
+    "--backup",
+    "--update",
+    "--journal",
+    "--atomic-batch",
+    "--dry-run",
+    "--edit",
+]);
+
+/// Rules of all options, checked in order, so that the first violation is
+/// reported. An option may have multiple rules.
+const OPTION_RULES: &[(&str, OptionRule)] = &[
+    (
+        "--on-conflict",
+        OptionRule::Excludes(&["--force", "--no-clobber", "--interactive", "--update"]),
+    ),
+    (
+        "--on-conflict=skip",
+        OptionRule::Excludes(&["--exchange", "--backup", "--atomic-batch"]),
+    ),
+    (
+        "--conflict-format",
+        OptionRule::Requires("--on-conflict=rename"),
+    ),
+    ("--output", OptionRule::Excludes(&["--json"])),
+    (
+        "--no-follow-parent-symlinks",
+        OptionRule::Excludes(&["--resolve"]),
+    ),
+    (
+        "--no-clobber",
+        OptionRule::Excludes(&[
+            "--force",
+            "--exchange",
+            "--backup",
+            "--atomic-batch",
+            "--update",
+        ]),
+    ),
+    (
+        "--target-directory",
+        OptionRule::Excludes(&["--no-target-directory", "--exchange"]),
+    ),
+    ("--whiteout", OptionRule::Excludes(&["--exchange"])),
+    (
+        "--from-file",
+        OptionRule::Excludes(&["--exchange", "--no-target-directory"]),
+    ),
+    (
+        "--from-file",
+        OptionRule::Replaces(
+            &["--expect-dev", "--expect-ino", "--expect-mtime"],
+            "--batch-expect",
+        ),
+    ),
+    (
+        "--exchange",
+        OptionRule::Excludes(&["--backup", "--update"]),
+    ),
+    ("--dry-run", OptionRule::Excludes(&["--output=json"])),
+    ("--interactive", OptionRule::Excludes(&["--atomic-batch"])),
+    ("--update", OptionRule::Excludes(&["--atomic-batch"])),
+    (
+        "--journal",
+        OptionRule::Excludes(&["--atomic-batch", "--dry-run"]),
+    ),
+    ("--keep-old", OptionRule::Requires("--replace-dir")),
+    ("--break-cycles", OptionRule::Requires("--reorder")),
+    (
+        "--reorder",
+        OptionRule::Excludes(&["--exchange", "--whiteout"]),
+    ),
+    (
+        "--edit",
+        OptionRule::Excludes(&[
+            "--exchange",
+            "--target-directory",
+            "--no-target-directory",
+            "--from-file",
+            "--rename",
+        ]),
+    ),
+    (
+        "--write-atomic",
+        OptionRule::Excludes(&[
+            "--undo",
+            "--exchange",
+            "--whiteout",
+            "--dry-run",
+            "--resolve",
+            "--src-dir",
+            "--dest-dir",
+            "--no-follow-parent-symlinks",
+            "--replace-dir",
+            "--symlink",
+            "--reorder",
+            "--edit",
+            "--target-directory",
+            "--no-target-directory",
+            "--from-file",
+            "--rename",
+        ]),
+    ),
+    ("--batch-expect", OptionRule::Requires("--from-file")),
+    ("--by-handle", EXPECTING_EXCLUDES),
+    // Located sources are absolute paths, which never stay beneath a base.
+    (
+        "--by-handle",
+        OptionRule::Excludes(&["--dest-dir", "--resolve", "--no-follow-parent-symlinks"]),
+    ),
+    ("--batch-expect", EXPECTING_EXCLUDES),
+    ("--expect-dev", EXPECTING_EXCLUDES),
+    ("--expect-ino", EXPECTING_EXCLUDES),
+    ("--expect-mtime", EXPECTING_EXCLUDES),
+    (
+        "--on-conflict=rename",
+        OptionRule::Excludes(&["--exchange", "--backup", "--atomic-batch"]),
+    ),
+    ("--src-dir", RESOLVING_EXCLUDES),
+    ("--dest-dir", RESOLVING_EXCLUDES),
+    ("--no-follow-parent-symlinks", RESOLVING_EXCLUDES),
+    ("--resolve", RESOLVING_EXCLUDES),
+    (
+        "--rename",
+        OptionRule::Excludes(&["--exchange", "--target-directory", "--no-target-directory"]),
+    ),
+    ("--undo", OptionRule::Only(&["--verbose"])),
+    (
+        "--replace-dir",
+        OptionRule::Only(&["--verbose", "--sync", "--keep-old"]),
+    ),
+    ("--symlink", OptionRule::Only(&["--verbose", "--sync"])),
+];
+
+/// Check `given` option names against [`OPTION_RULES`].
+///
+/// # Errors
+///
+/// Returns an error describing the first rule which is violated.
+pub fn check_option_rules(given: &[&str]) -> Result<()> {
+    for (name, rule) in OPTION_RULES {
+        if !given.contains(name) {
+            continue;
+        }
+        match rule {
+            OptionRule::Excludes(excluded) => {
+                if let Some(other) = excluded.iter().find(|other| given.contains(other)) {
+                    bail!("Cannot use '{other}' and '{name}' together");
+                }
+            }
+            OptionRule::Replaces(replaced, by) => {
+                if let Some(other) = replaced.iter().find(|other| given.contains(other)) {
+                    bail!("Cannot use '{other}' and '{name}' together, use '{by}' instead");
+                }
+            }
+            OptionRule::Requires(required) => {
+                ensure!(
+                    given.contains(required),
+                    "'{name}' can only be used together with '{required}'"
+                );
+            }
+            OptionRule::Only(allowed) => {
+                if given
+                    .iter()
+                    .any(|other| other != name && !allowed.contains(other))
+                {
+                    let list = (0..allowed.len())
+                        .map(|i| match i {
+                            0 => format!("'{}'", allowed[i]),
+                            _ if i + 1 == allowed.len() => format!(" and '{}'", allowed[i]),
+                            _ => format!(", '{}'", allowed[i]),
+                        })
+                        .collect::<String>();
+                    bail!("Only {list} can be used together with '{name}'");
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Check that a path still refers to the expected file right before renaming
+//! it, so that a file replaced in the meantime is not moved by mistake.
+//!
+//! The path is opened with `O_PATH` and inspected by statx(2) on the file
+//! descriptor. The check and the rename are still separate syscalls, so a
+//! replacement within this short window is not detected.
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use rustix::fs::{makedev, openat, statx, AtFlags, Mode, OFlags, StatxFlags, CWD};
+use rustix::io::Errno;
+
+use crate::{Error, ErrorKind, RenameMode};
+
+/// A modification time since the Unix epoch, written as `SEC[.FRACTION]`,
+/// eg. the output of `stat -c %.9Y`.
+///
+/// Like timestamps of the kernel, times before the epoch are rounded down to
+/// whole seconds, eg. `-1.5` is `sec = -2` and `nsec = 500000000`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mtime {
+    /// Whole seconds, rounded down.
+    pub sec: i64,
+    /// Nanoseconds after `sec`.
+    pub nsec: u32,
+}
+
+impl FromStr for Mtime {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseError(s.to_owned(), "expect 'SEC[.FRACTION]'");
+        let (sec, frac) = s.split_once('.').unwrap_or((s, "0"));
+        if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        let mut ret = Self {
+            sec: sec.parse().map_err(|_| err())?,
+            nsec: format!("{frac:0<9}").parse().map_err(|_| err())?,
+        };
+        // The fraction of a negative time counts towards the epoch, eg. of
+        // `-0.5`, whose seconds are not even negative.
+        if sec.starts_with('-') && ret.nsec != 0 {
+            ret.sec = ret.sec.checked_sub(1).ok_or_else(err)?;
+            ret.nsec = NSEC_PER_SEC - ret.nsec;
+        }
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for Mtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sec < 0 && self.nsec != 0 {
+            // The reverse of parsing.
+            write!(f, "-{}.{:09}", -(self.sec + 1), NSEC_PER_SEC - self.nsec)
+        } else {
+            write!(f, "{}.{:09}", self.sec, self.nsec)
+        }
+    }
+}
+
+const NSEC_PER_SEC: u32 = 1_000_000_000;
+
+/// Properties expected of a file. Properties which are `None` are not checked.
+///
+/// It is written as comma-separated `ino=N`, `dev=N` and `mtime=MTIME`, or `-`
+/// for nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Expected {
+    /// The device number.
+    pub dev: Option<u64>,
+    /// The inode number.
+    pub ino: Option<u64>,
+    /// The modification time.
+    pub mtime: Option<Mtime>,
+}
+
+/// The error of parsing an [`Expected`] or a [`Mtime`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String, &'static str);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid expectation '{}': {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for Expected {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut this = Self::default();
+        if s == "-" {
+            return Ok(this);
+        }
+        for field in s.split(',') {
+            let err = |reason| ParseError(field.to_owned(), reason);
+            let number = |value: &str| value.parse().map_err(|_| err("expect a number"));
+            match field.split_once('=') {
+                Some(("dev", value)) => this.dev = Some(number(value)?),
+                Some(("ino", value)) => this.ino = Some(number(value)?),
+                Some(("mtime", value)) => this.mtime = Some(value.parse()?),
+                _ => return Err(err("expect 'ino=N', 'dev=N' or 'mtime=SEC[.FRACTION]'")),
+            }
+        }
+        Ok(this)
+    }
+}
+
+impl Expected {
+    /// Take properties from `other` which are not given in `self`.
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            dev: self.dev.or(other.dev),
+            ino: self.ino.or(other.ino),
+            mtime: self.mtime.or(other.mtime),
+        }
+    }
+
+    /// Check `path` without following symlinks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of [`ErrorKind::Changed`] if any property differs,
+    /// which reports the inode actually found, or an error if `path` cannot be
+    /// inspected.
+    pub fn check(&self, path: &Path, mode: RenameMode) -> Result<(), Error> {
+        let fail = |err: Errno| Error::new(err.into(), mode);
+        let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+        let fd = openat(CWD, path, flags, Mode::empty()).map_err(fail)?;
+        let st = statx(
+            &fd,
+            "",
+            AtFlags::EMPTY_PATH,
+            StatxFlags::INO | StatxFlags::MTIME,
+        )
+        .map_err(fail)?;
+        let dev = makedev(st.stx_dev_major, st.stx_dev_minor);
+        let mtime = Mtime {
+            sec: st.stx_mtime.tv_sec,
+            nsec: st.stx_mtime.tv_nsec,
+        };
+        let (path, ino) = (path.display(), st.stx_ino);
+        let detail = match (self.ino, self.dev, self.mtime) {
+            (Some(expected), _, _) if expected != ino => {
+                format!("{path} has inode {ino} instead of {expected}")
+            }
+            (_, Some(expected), _) if expected != dev => {
+                format!("{path} (inode {ino}) is on device {dev} instead of {expected}")
+            }
+            (_, _, Some(expected)) if expected != mtime => {
+                format!("{path} (inode {ino}) has modification time {mtime} instead of {expected}")
+            }
+            _ => return Ok(()),
+        };
+        let mut err = Error::new(Errno::STALE.into(), mode).with_detail(detail);
+        err.kind = ErrorKind::Changed;
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, FileTimes};
+    use std::os::unix::fs::MetadataExt;
+    use std::time::{Duration, SystemTime};
+
+    use super::{Expected, Mtime};
+    use crate::testutil::ScratchDir;
+    use crate::{ErrorKind, RenameMode};
+
+    #[test]
+    fn test_parse() {
+        let mtime = |sec, nsec| Some(Mtime { sec, nsec });
+        assert_eq!("-".parse::<Expected>().unwrap(), Expected::default());
+        assert_eq!(
+            "ino=12,dev=3,mtime=1700000000.5"
+                .parse::<Expected>()
+                .unwrap(),
+            Expected {
+                dev: Some(3),
+                ino: Some(12),
+                mtime: mtime(1_700_000_000, 500_000_000),
+            }
+        );
+        assert_eq!("mtime=-1".parse::<Expected>().unwrap().mtime, mtime(-1, 0));
+        // Rounded down like the kernel does.
+        for (s, sec, nsec) in [
+            ("-1.5", -2, 500_000_000),
+            ("-0.25", -1, 750_000_000),
+            ("-1.000000001", -2, 999_999_999),
+            ("-1.0", -1, 0),
+        ] {
+            assert_eq!(s.parse().ok(), mtime(sec, nsec), "{s}");
+        }
+        for s in [
+            "-1.500000000",
+            "-0.250000000",
+            "-1.000000000",
+            "0.000000001",
+        ] {
+            assert_eq!(s.parse::<Mtime>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "1.000000001".parse::<Mtime>().unwrap().to_string(),
+            "1.000000001"
+        );
+        for (s, err) in [
+            (
+                "",
+                "Invalid expectation '': expect 'ino=N', 'dev=N' or 'mtime=SEC[.FRACTION]'",
+            ),
+            ("ino=x", "Invalid expectation 'ino=x': expect a number"),
+            (
+                "size=1",
+                "Invalid expectation 'size=1': expect 'ino=N', 'dev=N' or 'mtime=SEC[.FRACTION]'",
+            ),
+            (
+                "mtime=1.",
+                "Invalid expectation '1.': expect 'SEC[.FRACTION]'",
+            ),
+            (
+                "mtime=1.0000000001",
+                "Invalid expectation '1.0000000001': expect 'SEC[.FRACTION]'",
+            ),
+        ] {
+            assert_eq!(s.parse::<Expected>().unwrap_err().to_string(), err);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = ScratchDir::new("expect");
+        let path = dir.join("foo");
+        let time = SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 5);
+        File::create(&path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(time))
+            .unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        let mode = RenameMode::NoReplace;
+
+        Expected::default().check(&path, mode).unwrap();
+        let expected = Expected {
+            dev: Some(meta.dev()),
+            ino: Some(meta.ino()),
+            mtime: Some(Mtime {
+                sec: 1_000_000_000,
+                nsec: 5,
+            }),
+        };
+        expected.check(&path, mode).unwrap();
+
+        let err = Expected {
+            mtime: Some(Mtime::default()),
+            ..expected
+        }
+        .check(&path, mode)
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Changed);
+        assert!(
+            err.to_string()
+                .contains("has modification time 1000000000.000000005 instead of 0.000000000"),
+            "{err}",
+        );
+
+        // Before the epoch.
+        let time = SystemTime::UNIX_EPOCH - Duration::from_millis(1500);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(time))
+            .unwrap();
+        Expected {
+            mtime: Some("-1.5".parse().unwrap()),
+            ..Expected::default()
+        }
+        .check(&path, mode)
+        .unwrap();
+
+        // Replace it with another file.
+        fs::write(dir.join("bar"), "").unwrap();
+        fs::rename(dir.join("bar"), &path).unwrap();
+        let err = expected.check(&path, mode).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Changed);
+        assert!(err.to_string().contains("has inode"), "{err}");
+
+        fs::remove_file(&path).unwrap();
+        let err = expected.check(&path, mode).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Identify files by handles of `name_to_handle_at(2)`, which stay valid across
+//! renames, and find their current paths by `open_by_handle_at(2)`.
+//!
+//! Handles are written as `TYPE:HEX`, where `TYPE` is the decimal handle type
+//! and `HEX` is the opaque handle in hex. Opening a handle requires
+//! `CAP_DAC_READ_SEARCH`.
+//!
+//! The path is recovered from the directory entry cached by the kernel. Files
+//! other than directories have no link to their parents on disk, so if their
+//! entries are evicted, eg. under memory pressure or by dropping caches, the
+//! kernel either refuses to open them with `ESTALE`, or opens them
+//! disconnected without a usable path. Either way, the path cannot be
+//! recovered, and [`FileHandle::locate`] reports it instead of guessing.
+use std::ffi::{c_char, c_int, CString};
+use std::fmt;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rustix::fs::{self, Mode, OFlags};
+use rustix::io::Errno;
+
+use crate::expect::Expected;
+use crate::{Error, RenameMode};
+
+/// `MAX_HANDLE_SZ` of the kernel.
+const MAX_HANDLE_SIZE: usize = 128;
+
+/// The header of `struct file_handle`, followed by the opaque handle.
+#[repr(C)]
+struct RawHandle {
+    handle_bytes: u32,
+    handle_type: c_int,
+    f_handle: [u8; MAX_HANDLE_SIZE],
+}
+
+extern "C" {
+    fn name_to_handle_at(
+        dirfd: c_int,
+        pathname: *const c_char,
+        handle: *mut RawHandle,
+        mount_id: *mut c_int,
+        flags: c_int,
+    ) -> c_int;
+    fn open_by_handle_at(mount_fd: c_int, handle: *mut RawHandle, flags: c_int) -> c_int;
+}
+
+/// A handle which identifies a file within its filesystem.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FileHandle {
+    handle_type: i32,
+    bytes: Vec<u8>,
+}
+
+/// The error of parsing a [`FileHandle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid file handle '{}', expect 'TYPE:HEX'", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for FileHandle {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseError(s.to_owned());
+        let (handle_type, hex) = s.split_once(':').ok_or_else(err)?;
+        if hex.is_empty() || hex.len() % 2 != 0 || hex.len() / 2 > MAX_HANDLE_SIZE {
+            return Err(err());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(err)?;
+        Ok(Self {
+            handle_type: handle_type.parse().map_err(|_| err())?,
+            bytes,
+        })
+    }
+}
+
+impl fmt::Display for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.handle_type)?;
+        for b in &self.bytes {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FileHandle {
+    /// Get the handle of `path`, without following symlinks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` cannot be found, or the filesystem does not
+    /// support file handles.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut raw = RawHandle {
+            handle_bytes: MAX_HANDLE_SIZE.try_into().unwrap_or_default(),
+            handle_type: 0,
+            f_handle: [0; MAX_HANDLE_SIZE],
+        };
+        let mut mount_id = 0;
+        // SAFETY: `path` is NUL-terminated, and `raw` has room for
+        // `handle_bytes` bytes of the handle.
+        let ret = unsafe {
+            name_to_handle_at(
+                fs::CWD.as_raw_fd(),
+                path.as_ptr(),
+                &raw mut raw,
+                &raw mut mount_id,
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            handle_type: raw.handle_type,
+            bytes: raw.f_handle[..raw.handle_bytes as usize].to_vec(),
+        })
+    }
+
+    /// Open the file as an `O_PATH` file descriptor. `mount` is any path on
+    /// the same filesystem.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ESTALE` if the file no longer exists, `EPERM` without
+    /// `CAP_DAC_READ_SEARCH`, or an error if `mount` cannot be opened.
+    pub fn open(&self, mount: &Path) -> io::Result<OwnedFd> {
+        // `O_PATH` is rejected by `open_by_handle_at(2)`.
+        let mount_fd = fs::openat(
+            fs::CWD,
+            mount,
+            OFlags::RDONLY | OFlags::CLOEXEC,
+            Mode::empty(),
+        )?;
+        let mut raw = RawHandle {
+            handle_bytes: self.bytes.len().try_into().unwrap_or_default(),
+            handle_type: self.handle_type,
+            f_handle: [0; MAX_HANDLE_SIZE],
+        };
+        raw.f_handle[..self.bytes.len()].copy_from_slice(&self.bytes);
+        let flags = (OFlags::PATH | OFlags::CLOEXEC).bits().cast_signed();
+        // SAFETY: `raw` is a valid handle of `handle_bytes` bytes.
+        let fd = unsafe { open_by_handle_at(mount_fd.as_raw_fd(), &raw mut raw, flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a new file descriptor owned by nobody else.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Find the current path of the file, and the identity of it to be
+    /// checked before renaming. `mount` is any path on the same filesystem.
+    ///
+    /// Errors are reported as if they came from renaming in `mode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of [`ErrorKind::NotFound`](crate::ErrorKind) if the
+    /// file is unlinked but still open somewhere, an error if it no longer
+    /// exists or its path cannot be recovered (see the module documentation),
+    /// or any other error of [`FileHandle::open`].
+    pub fn locate(&self, mount: &Path, mode: RenameMode) -> Result<(PathBuf, Expected), Error> {
+        let unrecoverable = || {
+            Error::new(Errno::STALE.into(), mode).with_detail(
+                "The file no longer exists, or its path cannot be recovered since the kernel has \
+                 no cached directory entry of it, eg. after caches are dropped"
+                    .into(),
+            )
+        };
+        let fd = self.open(mount).map_err(|err| {
+            if err.raw_os_error() == Some(Errno::STALE.raw_os_error()) {
+                unrecoverable()
+            } else {
+                Error::new(err, mode)
+            }
+        })?;
+        let st = fs::fstat(&fd).map_err(|err| Error::new(err.into(), mode))?;
+        let unlinked =
+            || Error::new(Errno::NOENT.into(), mode).with_detail("The file is unlinked".into());
+        if st.st_nlink == 0 {
+            return Err(unlinked());
+        }
+        let path = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+            .map_err(|err| Error::new(err, mode))?;
+        if path.as_os_str().as_bytes().ends_with(b" (deleted)") {
+            return Err(unlinked());
+        }
+        let expected = Expected {
+            dev: Some(st.st_dev),
+            ino: Some(st.st_ino),
+            mtime: None,
+        };
+        // A disconnected file gets a path which does not lead to it.
+        if expected.check(&path, mode).is_err() {
+            return Err(unrecoverable());
+        }
+        Ok((path, expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::FileHandle;
+    use crate::testutil::ScratchDir;
+    use crate::{ErrorKind, RenameMode};
+
+    #[test]
+    fn test_parse() {
+        let handle = "1:00ff".parse::<FileHandle>().unwrap();
+        assert_eq!(handle.handle_type, 1);
+        assert_eq!(handle.bytes, [0x00, 0xff]);
+        assert_eq!(handle.to_string(), "1:00ff");
+        for s in ["1", "x:00", "1:0", "1:", "1:zz", "1:é0"] {
+            assert_eq!(
+                s.parse::<FileHandle>().unwrap_err().to_string(),
+                format!("Invalid file handle '{s}', expect 'TYPE:HEX'"),
+            );
+        }
+    }
+
+    #[test]
+    fn test_locate() {
+        let dir = ScratchDir::new("handle");
+        fs::write(dir.join("foo"), "").unwrap();
+
+        let handle = match FileHandle::of(&dir.join("foo")) {
+            Ok(handle) => handle,
+            // Not supported by the filesystem.
+            Err(err) if err.raw_os_error() == Some(rustix::io::Errno::OPNOTSUPP.raw_os_error()) => {
+                return
+            }
+            Err(err) => panic!("{err}"),
+        };
+        fs::rename(dir.join("foo"), dir.join("bar")).unwrap();
+        match handle.locate(&dir, RenameMode::NoReplace) {
+            Ok((path, _)) => assert_eq!(path, dir.canonicalize().unwrap().join("bar")),
+            // Lacking CAP_DAC_READ_SEARCH.
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {}
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! An append-only record of completed renames, so that they can be undone.
+//!
+//! Each entry consists of three NUL-terminated fields: the mode, the source
+//! and the destination. Paths are stored as absolute paths, so the journal can
+//! be replayed from any working directory.
+//!
+//! A crash may leave a truncated last entry, which is ignored when reading.
+//! It is also cut off before appending anything, since new entries after it
+//! would be misaligned otherwise.
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::{RenameMode, RenameOp};
+
+/// A journal opened for appending.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    cwd: PathBuf,
+}
+
+impl Journal {
+    /// Open a journal for appending, creating it if it doesn't exist. A
+    /// truncated last entry is removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or truncated, or the
+    /// current directory is inaccessible.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let (_, complete_len) = read_entries(&file)?;
+        if complete_len != file.metadata()?.len() {
+            file.set_len(complete_len)?;
+            file.sync_data()?;
+        }
+        let cwd = std::env::current_dir()?;
+        Ok(Self { file, cwd })
+    }
+
+    /// Append a completed operation and flush it to the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or syncing the journal fails.
+    pub fn record(&mut self, op: &RenameOp) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for field in [
+            Path::new(mode_name(op.mode)),
+            &self.cwd.join(&op.src),
+            &self.cwd.join(&op.dest),
+        ] {
+            buf.extend_from_slice(field.as_os_str().as_bytes());
+            buf.push(b'\0');
+        }
+        // Write the whole entry at once, so that a crash leaves at most a
+        // truncated last entry.
+        self.file.write_all(&buf)?;
+        self.file.sync_data()
+    }
+}
+
+/// Read all complete entries of a journal, in the order they were recorded.
+/// A truncated last entry, which may be left by a crash, is ignored.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains an invalid entry.
+pub fn read(path: &Path) -> io::Result<Vec<RenameOp>> {
+    let (raw_entries, _) = read_entries(File::open(path)?)?;
+    let mut entries = Vec::with_capacity(raw_entries.len());
+    for [mode, src, dest] in raw_entries {
+        let mode = parse_mode(&mode).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid mode in journal: {:?}",
+                    String::from_utf8_lossy(&mode)
+                ),
+            )
+        })?;
+        let src = PathBuf::from(OsString::from_vec(src));
+        let dest = PathBuf::from(OsString::from_vec(dest));
+        entries.push(RenameOp::new(src, dest, mode));
+    }
+    Ok(entries)
+}
+
+/// Read the fields of all complete entries, and the length of them in bytes.
+fn read_entries(file: impl Read) -> io::Result<(Vec<[Vec<u8>; 3]>, u64)> {
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::new();
+    let mut complete_len = 0;
+    let mut entry_len = 0;
+    let mut fields = Vec::with_capacity(3);
+    loop {
+        let mut field = Vec::new();
+        let len = reader.read_until(b'\0', &mut field)?;
+        if len == 0 || field.pop() != Some(b'\0') {
+            break;
+        }
+        entry_len += len as u64;
+        fields.push(field);
+        match <[Vec<u8>; 3]>::try_from(std::mem::take(&mut fields)) {
+            Ok(entry) => {
+                entries.push(entry);
+                complete_len += entry_len;
+                entry_len = 0;
+            }
+            // Not complete yet.
+            Err(incomplete) => fields = incomplete,
+        }
+    }
+    Ok((entries, complete_len))
+}
+
+fn mode_name(mode: RenameMode) -> &'static str {
+    match mode {
+        RenameMode::Replace => "replace",
+        RenameMode::NoReplace => "noreplace",
+        RenameMode::Exchange => "exchange",
+        RenameMode::Whiteout { replace: false } => "whiteout",
+        RenameMode::Whiteout { replace: true } => "whiteout-replace",
+    }
+}
+
+fn parse_mode(name: &[u8]) -> Option<RenameMode> {
+    Some(match name {
+        b"replace" => RenameMode::Replace,
+        b"noreplace" => RenameMode::NoReplace,
+        b"exchange" => RenameMode::Exchange,
+        b"whiteout" => RenameMode::Whiteout { replace: false },
+        b"whiteout-replace" => RenameMode::Whiteout { replace: true },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use super::{read, Journal};
+    use crate::testutil::ScratchDir;
+    use crate::{RenameMode, RenameOp};
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = ScratchDir::new("journal");
+        let path = dir.join("journal");
+
+        let ops = [
+            RenameOp::new("/foo", "/bar", RenameMode::NoReplace),
+            RenameOp::new("/a\nb", "/c d", RenameMode::Exchange),
+            RenameOp::new("/x", "/y", RenameMode::Whiteout { replace: true }),
+        ];
+        let mut journal = Journal::open(&path).unwrap();
+        journal.record(&ops[0]).unwrap();
+        journal.record(&ops[1]).unwrap();
+        drop(journal);
+        let mut journal = Journal::open(&path).unwrap();
+        journal.record(&ops[2]).unwrap();
+        assert_eq!(read(&path).unwrap(), ops);
+
+        // A truncated entry is ignored.
+        journal.file.write_all(b"replace\0/foo\0/ba").unwrap();
+        assert_eq!(read(&path).unwrap(), ops);
+        drop(journal);
+
+        // It is cut off before appending.
+        let mut journal = Journal::open(&path).unwrap();
+        journal.record(&ops[0]).unwrap();
+        let mut expect = ops.to_vec();
+        expect.push(ops[0].clone());
+        assert_eq!(read(&path).unwrap(), expect);
+        fs::write(&path, "replace\0/x\0").unwrap();
+        let mut journal = Journal::open(&path).unwrap();
+        journal.record(&ops[1]).unwrap();
+        assert_eq!(read(&path).unwrap(), [ops[1].clone()]);
+
+        // Relative paths are resolved against the current directory.
+        let cwd = std::env::current_dir().unwrap();
+        fs::write(&path, "").unwrap();
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .record(&RenameOp::new("foo", "bar", RenameMode::Replace))
+            .unwrap();
+        assert_eq!(
+            read(&path).unwrap(),
+            [RenameOp::new(
+                cwd.join("foo"),
+                cwd.join("bar"),
+                RenameMode::Replace
+            )],
+        );
+
+        fs::write(&path, "move\0/foo\0/bar\0").unwrap();
+        assert_eq!(
+            read(&path).unwrap_err().to_string(),
+            "Invalid mode in journal: \"move\"",
+        );
+    }
+}
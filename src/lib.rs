@@ -0,0 +1,565 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! mv(1) but without cp(1) fallback. Simple wrapper of renameat2(2).
+//!
+//! This library exposes the same rename semantics as the `rawmv` command, so
+//! that other tools can link against it instead of spawning a process.
+//!
+//! ```no_run
+//! use rawmv::{RenameMode, RenameOp};
+//!
+//! let op = RenameOp::new("foo", "bar", RenameMode::NoReplace);
+//! if let Err(err) = op.execute() {
+//!     eprintln!("Cannot rename: {err}");
+//! }
+//! ```
+#![warn(clippy::pedantic)]
+use std::fmt;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use rustix::fs::{self, RenameFlags};
+use rustix::io::Errno;
+
+use crate::resolve::Resolver;
+
+pub mod backup;
+pub mod batch;
+pub mod expect;
+pub mod handle;
+pub mod journal;
+pub mod mount;
+mod names;
+pub mod plan;
+pub mod replace;
+pub mod resolve;
+pub mod subst;
+pub mod symlink;
+pub mod sync;
+pub mod unique;
+pub mod update;
+pub mod write;
+
+#[cfg(test)]
+mod testutil;
+
+/// How a [`RenameOp`] treats its destination.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RenameMode {
+    /// Atomically replace the destination if it exists.
+    Replace,
+    /// Fail with [`ErrorKind::AlreadyExists`] if the destination exists.
+    #[default]
+    NoReplace,
+    /// Atomically exchange the source and the destination. Both must exist.
+    Exchange,
+    /// Move the source and leave a whiteout device in its place. This is
+    /// used for overlayfs upper layers and requires `CAP_MKNOD`.
+    Whiteout {
+        /// Whether to replace the destination if it exists.
+        replace: bool,
+    },
+}
+
+impl RenameMode {
+    /// The flags passed to renameat2(2) for this mode.
+    #[must_use]
+    pub fn flags(self) -> RenameFlags {
+        match self {
+            Self::Replace => RenameFlags::empty(),
+            Self::NoReplace => RenameFlags::NOREPLACE,
+            Self::Exchange => RenameFlags::EXCHANGE,
+            Self::Whiteout { replace: true } => RenameFlags::WHITEOUT,
+            Self::Whiteout { replace: false } => RenameFlags::WHITEOUT | RenameFlags::NOREPLACE,
+        }
+    }
+
+    /// The names of the renameat2(2) flags for this mode.
+    #[must_use]
+    pub fn flag_names(self) -> &'static [&'static str] {
+        match self {
+            Self::Replace => &[],
+            Self::NoReplace => &["RENAME_NOREPLACE"],
+            Self::Exchange => &["RENAME_EXCHANGE"],
+            Self::Whiteout { replace: true } => &["RENAME_WHITEOUT"],
+            Self::Whiteout { replace: false } => &["RENAME_NOREPLACE", "RENAME_WHITEOUT"],
+        }
+    }
+
+    /// Whether this mode refuses to replace an existing destination.
+    #[must_use]
+    pub fn is_noreplace(self) -> bool {
+        self.flags().contains(RenameFlags::NOREPLACE)
+    }
+
+    /// The same mode, but replacing an existing destination.
+    #[must_use]
+    pub fn replacing(self) -> Self {
+        match self {
+            Self::NoReplace => Self::Replace,
+            Self::Whiteout { .. } => Self::Whiteout { replace: true },
+            mode => mode,
+        }
+    }
+}
+
+/// A single rename operation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenameOp {
+    /// The path to be moved.
+    pub src: PathBuf,
+    /// The path to move to.
+    pub dest: PathBuf,
+    /// How to treat the destination.
+    pub mode: RenameMode,
+}
+
+impl RenameOp {
+    /// Create a new operation.
+    pub fn new(src: impl Into<PathBuf>, dest: impl Into<PathBuf>, mode: RenameMode) -> Self {
+        Self {
+            src: src.into(),
+            dest: dest.into(),
+            mode,
+        }
+    }
+
+    /// Check whether [`RenameOp::execute`] would likely succeed, without
+    /// modifying anything. The filesystem may still change in between, so
+    /// this is only advisory.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that [`RenameOp::execute`] is expected to return.
+    pub fn preflight(&self) -> Result<(), Error> {
+        self.preflight_with(|path| match path.symlink_metadata() {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        })
+    }
+
+    /// Same as [`RenameOp::preflight`], but checks the existence of paths by
+    /// `exists`. This is useful to simulate a sequence of operations.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that [`RenameOp::execute`] is expected to return, or
+    /// the error returned by `exists`.
+    pub fn preflight_with(
+        &self,
+        mut exists: impl FnMut(&Path) -> io::Result<bool>,
+    ) -> Result<(), Error> {
+        let mut exists = |path: &Path| exists(path).map_err(|err| Error::new(err, self.mode));
+        if !exists(&self.src)? {
+            return Err(Error::new(Errno::NOENT.into(), self.mode));
+        }
+        let dest_dir = names::parent(&self.dest);
+        if !exists(dest_dir)? {
+            return Err(Error::new(Errno::NOENT.into(), self.mode));
+        }
+        // A directory which only exists after previous operations cannot be
+        // inspected, and is assumed to be a directory.
+        if dest_dir.metadata().is_ok_and(|meta| !meta.is_dir()) {
+            return Err(Error::new(Errno::NOTDIR.into(), self.mode));
+        }
+        let dest_exists = exists(&self.dest)?;
+        if self.mode == RenameMode::Exchange && !dest_exists {
+            return Err(Error::new(Errno::NOENT.into(), self.mode));
+        }
+        if self.mode.is_noreplace() && dest_exists {
+            return Err(Error::new(Errno::EXIST.into(), self.mode));
+        }
+        mount::check(&self.src, &self.dest, self.mode)
+    }
+
+    /// Perform the rename with a single renameat2(2) call. It never falls back
+    /// to copying.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying syscall fails. Nothing is changed on
+    /// the filesystem in this case. For `EXDEV`, the mounts involved are
+    /// described in [`Error::hint`] if possible.
+    pub fn execute(&self) -> Result<(), Error> {
+        fs::renameat_with(fs::CWD, &self.src, fs::CWD, &self.dest, self.mode.flags())
+            .map_err(|errno| self.rename_error(errno, || mount::explain(&self.src, &self.dest)))
+    }
+
+    /// Same as [`RenameOp::execute`], but resolve the parent directories of
+    /// the source and the destination by `src_dir` and `dest_dir`
+    /// respectively, and rename relative to them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of [`ErrorKind::Restricted`] if a parent directory
+    /// cannot be resolved within the restrictions, or any error of
+    /// [`RenameOp::execute`]. For `EXDEV`, the mounts of the opened parent
+    /// directories are described.
+    pub fn execute_with(&self, src_dir: &Resolver, dest_dir: &Resolver) -> Result<(), Error> {
+        let (src_fd, src_name) = src_dir
+            .open_parent(&self.src)
+            .map_err(|err| Error::resolving(err, &self.src, self.mode))?;
+        let (dest_fd, dest_name) = dest_dir
+            .open_parent(&self.dest)
+            .map_err(|err| Error::resolving(err, &self.dest, self.mode))?;
+        fs::renameat_with(&src_fd, src_name, &dest_fd, dest_name, self.mode.flags())
+            .map_err(|errno| self.rename_error(errno, || mount::explain_dirs(&src_fd, &dest_fd)))
+    }
+
+    /// Classify a failure of renameat2(2), and describe the mounts by
+    /// `explain` for `EXDEV`.
+    fn rename_error(&self, errno: Errno, explain: impl FnOnce() -> Option<String>) -> Error {
+        let err = Error::new(io::Error::from(errno), self.mode);
+        if errno != Errno::XDEV {
+            return err;
+        }
+        match explain() {
+            Some(detail) => err.with_detail(detail),
+            None => err,
+        }
+    }
+
+    /// Reverse this operation after it was executed successfully. Moving back
+    /// never replaces anything, so a destination replaced by the original
+    /// operation is not restored.
+    ///
+    /// For [`RenameMode::Whiteout`], the source is atomically exchanged back
+    /// with the whiteout, which is then removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the paths have changed since, so that the operation
+    /// can no longer be reversed.
+    pub fn revert(&self) -> Result<(), Error> {
+        match self.mode {
+            RenameMode::Exchange => self.execute(),
+            RenameMode::Whiteout { .. } => {
+                let is_whiteout = self
+                    .src
+                    .symlink_metadata()
+                    .is_ok_and(|meta| meta.file_type().is_char_device() && meta.rdev() == 0);
+                if !is_whiteout {
+                    return Err(Error::new(Errno::EXIST.into(), RenameMode::NoReplace));
+                }
+                Self::new(&self.dest, &self.src, RenameMode::Exchange).execute()?;
+                std::fs::remove_file(&self.dest).map_err(|err| Error::new(err, self.mode))
+            }
+            RenameMode::Replace | RenameMode::NoReplace => {
+                Self::new(&self.dest, &self.src, RenameMode::NoReplace).execute()
+            }
+        }
+    }
+}
+
+/// The category of an [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The destination exists and the mode refuses to replace it.
+    AlreadyExists,
+    /// The source, the destination or one of their parents does not exist.
+    NotFound,
+    /// The source and the destination are on different mounts.
+    CrossDevice,
+    /// Permission is denied, or a required capability is missing.
+    PermissionDenied,
+    /// A parent directory cannot be resolved within the restrictions of a
+    /// [`Resolver`].
+    Restricted,
+    /// The source is not the file expected, since it has been replaced or
+    /// modified.
+    Changed,
+    /// The filesystem does not support the requested mode.
+    Unsupported,
+    /// Any other error.
+    Other,
+}
+
+/// The error type of [`RenameOp::execute`].
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    mode: RenameMode,
+    source: io::Error,
+    detail: Option<String>,
+}
+
+impl Error {
+    /// Classify an I/O error of an operation in `mode`, eg. of a check done
+    /// before renaming.
+    #[must_use]
+    pub fn new(source: io::Error, mode: RenameMode) -> Self {
+        let kind = match Errno::from_io_error(&source) {
+            Some(Errno::EXIST) => ErrorKind::AlreadyExists,
+            Some(Errno::NOENT) => ErrorKind::NotFound,
+            Some(Errno::XDEV) => ErrorKind::CrossDevice,
+            Some(Errno::PERM | Errno::ACCESS) => ErrorKind::PermissionDenied,
+            Some(Errno::INVAL) if mode != RenameMode::Replace && mode != RenameMode::NoReplace => {
+                ErrorKind::Unsupported
+            }
+            _ => ErrorKind::Other,
+        };
+        Self {
+            kind,
+            mode,
+            source,
+            detail: None,
+        }
+    }
+
+    /// Classify an error of [`Resolver::open_parent`].
+    fn resolving(source: io::Error, path: &Path, mode: RenameMode) -> Self {
+        let path = path.display();
+        let detail = match Errno::from_io_error(&source) {
+            Some(Errno::XDEV) => {
+                format!("The parent of {path} escapes the base directory or crosses a mount")
+            }
+            Some(Errno::LOOP) => format!("The parent of {path} contains symlinks"),
+            Some(Errno::INVAL) => format!("{path} does not end with a normal name"),
+            _ => return Self::new(source, mode),
+        };
+        let mut err = Self::new(source, mode).with_detail(detail);
+        err.kind = ErrorKind::Restricted;
+        err
+    }
+
+    fn with_detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+
+    /// The category of this error.
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The mode of the failed operation.
+    #[must_use]
+    pub fn mode(&self) -> RenameMode {
+        self.mode
+    }
+
+    /// The raw errno returned by the kernel.
+    #[must_use]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.source.raw_os_error()
+    }
+
+    /// The underlying I/O error.
+    #[must_use]
+    pub fn io_error(&self) -> &io::Error {
+        &self.source
+    }
+
+    /// Explain errors whose meaning depends on the rename mode or the
+    /// environment, since the bare errno is confusing for these cases.
+    #[must_use]
+    pub fn hint(&self) -> Option<&str> {
+        if let Some(detail) = &self.detail {
+            return Some(detail);
+        }
+        match (self.mode, self.kind) {
+            (RenameMode::Exchange, ErrorKind::NotFound) => {
+                Some("Both paths must exist to be exchanged")
+            }
+            (RenameMode::Exchange, ErrorKind::Unsupported) => {
+                Some("The filesystem may not support RENAME_EXCHANGE")
+            }
+            (RenameMode::Whiteout { .. }, ErrorKind::PermissionDenied)
+                if self.raw_os_error() == Some(Errno::PERM.raw_os_error()) =>
+            {
+                Some("Creating a whiteout requires CAP_MKNOD")
+            }
+            (RenameMode::Whiteout { .. }, ErrorKind::Unsupported) => {
+                Some("The filesystem may not support RENAME_WHITEOUT")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.hint() {
+            Some(hint) => write!(f, "{hint}: {}", self.source),
+            None => self.source.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use std::path::Path;
+
+    use rustix::fs::ResolveFlags;
+
+    use super::{ErrorKind, RenameMode, RenameOp};
+    use crate::resolve::Resolver;
+    use crate::testutil::ScratchDir;
+
+    /// Create an empty scratch directory on tmpfs, which supports whiteouts.
+    fn tmpfs_dir(name: &str) -> Option<ScratchDir> {
+        let shm = Path::new("/dev/shm");
+        if !shm.is_dir() {
+            eprintln!("skipped: /dev/shm is unavailable");
+            return None;
+        }
+        Some(ScratchDir::new_in(shm, name))
+    }
+
+    #[test]
+    fn test_preflight_parent() {
+        let dir = ScratchDir::new("preflight-parent");
+        let src = dir.join("src");
+        fs::write(&src, "").unwrap();
+
+        // It fails just like renaming.
+        for dest in [dir.join("missing/dest"), dir.join("src/dest")] {
+            let op = RenameOp::new(&src, &dest, RenameMode::NoReplace);
+            let expected = op.execute().unwrap_err();
+            let err = op.preflight().unwrap_err();
+            assert_eq!(err.raw_os_error(), expected.raw_os_error());
+            assert_eq!(err.kind(), expected.kind());
+        }
+        RenameOp::new(&src, "dest-in-cwd", RenameMode::NoReplace)
+            .preflight()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_execute_with_cross_device() {
+        let Some(shm) = tmpfs_dir("execute-with-xdev") else {
+            return;
+        };
+        let dir = ScratchDir::new("execute-with-xdev");
+        let src = dir.join("src");
+        fs::write(&src, "").unwrap();
+
+        // Explained just like renaming by paths.
+        let op = RenameOp::new(&src, shm.join("dest"), RenameMode::NoReplace);
+        let expected = op.execute().unwrap_err();
+        if expected.kind() != ErrorKind::CrossDevice {
+            eprintln!("skipped: {} is on tmpfs", dir.display());
+            return;
+        }
+        let resolver = Resolver::new(ResolveFlags::empty());
+        let err = op.execute_with(&resolver, &resolver).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CrossDevice);
+        assert!(err.hint().is_some());
+        assert_eq!(err.hint(), expected.hint());
+    }
+
+    #[test]
+    fn test_exchange() {
+        let Some(dir) = tmpfs_dir("exchange") else {
+            return;
+        };
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "a").unwrap();
+
+        let err = RenameOp::new(&a, &b, RenameMode::Exchange)
+            .execute()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.hint(), Some("Both paths must exist to be exchanged"));
+        let err = RenameOp::new(&a, &b, RenameMode::Exchange)
+            .preflight()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        fs::write(&b, "b").unwrap();
+        RenameOp::new(&a, &b, RenameMode::Exchange)
+            .preflight()
+            .unwrap();
+        RenameOp::new(&a, &b, RenameMode::Exchange)
+            .execute()
+            .unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+
+        RenameOp::new(&a, &b, RenameMode::Exchange)
+            .revert()
+            .unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+    }
+
+    #[test]
+    fn test_revert() {
+        let Some(dir) = tmpfs_dir("revert") else {
+            return;
+        };
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "a").unwrap();
+
+        let op = RenameOp::new(&a, &b, RenameMode::NoReplace);
+        op.execute().unwrap();
+        fs::write(&a, "new").unwrap();
+        let err = op.revert().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        fs::remove_file(&a).unwrap();
+        op.revert().unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_whiteout() {
+        let Some(dir) = tmpfs_dir("whiteout") else {
+            return;
+        };
+        let (src, dest) = (dir.join("src"), dir.join("dest"));
+        fs::write(&src, "foo").unwrap();
+
+        let op = RenameOp::new(&src, &dest, RenameMode::Whiteout { replace: false });
+        match op.execute() {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                eprintln!("skipped: {err}");
+                return;
+            }
+            Err(err) => panic!("{err}"),
+        }
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "foo");
+        let meta = fs::symlink_metadata(&src).unwrap();
+        assert!(meta.file_type().is_char_device());
+        assert_eq!(meta.rdev(), 0);
+
+        // NOREPLACE still applies to the destination.
+        fs::remove_file(&src).unwrap();
+        fs::write(&src, "bar").unwrap();
+        let err = op.preflight().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let err = op.execute().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&src).unwrap(), "bar");
+
+        let op = RenameOp {
+            mode: op.mode.replacing(),
+            ..op
+        };
+        op.execute().unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "bar");
+        assert!(fs::symlink_metadata(&src)
+            .unwrap()
+            .file_type()
+            .is_char_device());
+
+        op.revert().unwrap();
+        assert_eq!(fs::read_to_string(&src).unwrap(), "bar");
+        assert!(!dest.exists());
+        let err = op.revert().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+}
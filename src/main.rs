@@ -2,14 +2,44 @@
 #![warn(clippy::pedantic)]
 #![allow(unknown_lints)]
 #![allow(clippy::tuple_array_conversions)]
+#![allow(clippy::unnecessary_debug_formatting)]
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::ffi::OsString;
-use std::io::{self, Write};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 use std::process;
 
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use pico_args::Arguments;
+use rawmv::backup::BackupControl;
+use rawmv::batch::{Batch, Decision, Observer, OnExists, Outcome, Prediction, Report, Summary};
+use rawmv::expect::Expected;
+use rawmv::handle::FileHandle;
+use rawmv::journal::{self, Journal};
+use rawmv::plan::{self, CycleStrategy};
+use rawmv::replace;
+use rawmv::resolve::Resolver;
+use rawmv::subst::Substitution;
+use rawmv::symlink;
+use rawmv::sync::DirSync;
+use rawmv::unique::NameFormat;
+use rawmv::update::Update;
+use rawmv::write;
+use rawmv::{RenameMode, RenameOp};
+use rustix::fs::ResolveFlags;
+
+use crate::cli::edit;
+use crate::cli::prompt::Prompt;
+use crate::cli::report;
+use crate::cli::rules::check_option_rules;
+
+mod cli;
+#[cfg(test)]
+mod testutil;
 
 // We truly want boolean productions, not one-at-a-time.
 // See: https://github.com/rust-lang/rust-clippy/issues/10923
@@ -20,10 +50,37 @@ struct App {
     no_clobber: bool,
     interactive: bool,
     verbose: bool,
+    dry_run: bool,
+    json: bool,
+    atomic_batch: bool,
+    exchange: bool,
+    whiteout: bool,
+    replace_dir: bool,
+    symlink: bool,
+    write_atomic: Option<PathBuf>,
+    journal: Option<PathBuf>,
+    undo: Option<PathBuf>,
+    reorder: Option<CycleStrategy>,
+    edit: Option<Vec<PathBuf>>,
+    backup: Option<BackupControl>,
+    suffix: Option<OsString>,
+    update: Option<Update>,
+    unique: Option<NameFormat>,
+    keep_old: Option<OsString>,
+    by_handle: Option<PathBuf>,
+    handle_sources: Vec<HandleSource>,
+    expect: HashMap<PathBuf, Expected>,
+    src_dir: Option<PathBuf>,
+    dest_dir: Option<PathBuf>,
+    resolve: Option<ResolveFlags>,
+    no_follow_parent_symlinks: bool,
+    sync: bool,
     operations: Vec<(PathBuf, PathBuf)>,
 }
 
 impl App {
+    // It is a single string literal.
+    #[allow(clippy::too_many_lines)]
     fn help() -> String {
         format!(
             "\
@@ -34,22 +91,215 @@ USAGE:
     rawmv [OPTION]... [-T] <SOURCE> <DEST>
     rawmv [OPTION]... <SOURCE>... <DIRECTORY>
     rawmv [OPTION]... -t <DIRECTORY> <SOURCE>...
+    rawmv [OPTION]... --exchange <PATH1> <PATH2>
+    rawmv [OPTION]... --replace-dir <NEW> <OLD>
+    rawmv [OPTION]... --symlink <TARGET> <LINKNAME>
+    rawmv [OPTION]... --write-atomic <DEST>
+    rawmv [OPTION]... --reorder [--break-cycles <STRATEGY>] ...
+    rawmv [OPTION]... [-t <DIRECTORY>] --from-file <FILE>
+    rawmv [OPTION]... --rename <EXPRESSION> <SOURCE>...
+    rawmv [OPTION]... --edit [PATH]...
+    rawmv [-v] --undo <JOURNAL>
 
 FLAGS:
+        --atomic-batch          Preflight all operations before renaming
+                                anything, and if any of them fails, roll back
+                                completed ones in reverse order. Destinations
+                                replaced by '--force' cannot be restored, use
+                                '--backup' to keep them
+        --batch-expect          Read an expectation after each source and
+                                destination pair, or each source with
+                                '--target-directory', from '--from-file'. It
+                                is comma-separated 'ino=N', 'dev=N' and
+                                'mtime=SEC[.FRACTION]', or '-' for nothing,
+                                and is checked like '--expect-ino'
+        --edit                  Edit names of PATHs, or entries of them if
+                                they are directories, in $VISUAL or $EDITOR.
+                                Each changed line is renamed to the new name.
+                                The current directory is used if no PATH is
+                                given
+        --exchange              Atomically exchange two existing paths using
+                                RENAME_EXCHANGE. Exactly two operands are
+                                expected and both must exist
+    -b                          Like '--backup' but does not accept a value
     -f, --force                 Do not prompt before overwriting. Note that
                                 unlike mv(1), without this flag, we raise an
                                 error if the destination already exists
     -h, --help                  Prints help informatio.
-    -i, --interactive           Prompt for confirmation before overwrite
+    -i, --interactive           Prompt for confirmation before overwrite.
+                                Answers are read from the terminal. Enter
+                                '?' at the prompt for all answers
+        --json                  Same as '--output=json'
     -n, --no-clobber            Silently skip files whose destinations exist
+        --no-follow-parent-symlinks
+                                Walk parent directories of each path one
+                                component at a time with O_NOFOLLOW, refuse
+                                any symlink on the way, and rename relative
+                                to the verified parents. Unlike '--resolve',
+                                it works without openat2(2). With
+                                '--src-dir' or '--dest-dir', '..' and
+                                absolute paths are refused as well
+    -N, --dry-run               Print the planned operations and check them
+                                without touching the filesystem. Exit with
+                                the status that a real run would likely give
+        --reorder               Order operations so that destinations which
+                                are also sources are moved away first. This
+                                allows swapping and rotating names, eg. from
+                                '--rename' or '--from-file'
+        --replace-dir           Replace the directory OLD with the directory
+                                NEW atomically using RENAME_EXCHANGE, so
+                                readers always see a complete tree, then
+                                remove the old tree left at NEW, unless
+                                '--keep-old' is given. If OLD does not exist,
+                                NEW is simply renamed to it
+        --symlink               Point the symlink LINKNAME to TARGET
+                                atomically, like 'ln -sfn' but LINKNAME never
+                                disappears in between. A temporary symlink is
+                                created next to it and renamed over it
+        --sync                  After all operations, fsync(2) parent
+                                directories of sources and destinations,
+                                each only once, so that completed renames
+                                survive a power failure
     -T, --no-target-directory   Always treat the last path (destination) as a
                                 normal file. This implies that only two
                                 operands are expected
+    -u                          Like '--update' but does not accept a value
     -V, --version               Prints version information
     -v, --verbose               Print what is being done
+        --whiteout              Leave a whiteout device at the source after
+                                moving, using RENAME_WHITEOUT. This is useful
+                                for maintaining overlayfs upper layers and
+                                requires CAP_MKNOD
+        --write-atomic          Write stdin to a hidden temporary file in the
+                                directory of DEST, copying the mode and the
+                                owner of DEST if it exists, then rename it to
+                                DEST like other operations. The file is
+                                fsync(2)ed with '--sync'
 
 OPTIONS:
+        --backup[=<CONTROL>]            Rename each existing destination aside
+                                        before overwriting it. CONTROL is one
+                                        of 'none', 'simple', 'numbered' or
+                                        'existing' (default), or taken from
+                                        $VERSION_CONTROL. Backups are made by
+                                        renames which never replace anything,
+                                        so a simple backup fails if the old
+                                        one still exists
+        --by-handle <MOUNT>             Take sources as file handles from
+                                        name_to_handle_at(2), written as
+                                        'TYPE:HEX', on the filesystem of
+                                        MOUNT. Each is opened by
+                                        open_by_handle_at(2) to find its
+                                        current path, and is refused if it is
+                                        unlinked. Right before renaming, the
+                                        path is checked to still be the same
+                                        inode. This requires
+                                        CAP_DAC_READ_SEARCH. Paths of files
+                                        other than directories can only be
+                                        found while the kernel caches them,
+                                        so handles kept for long, or across
+                                        dropping caches, may fail
+        --break-cycles <STRATEGY>       How '--reorder' breaks cycles like
+                                        'a -> b -> a'. 'exchange' (default)
+                                        uses RENAME_EXCHANGE, and 'temp' uses
+                                        a temporary name in the same directory
+        --conflict-format <FORMAT>      Names tried by '--on-conflict=rename',
+                                        where '{{name}}' is the file name
+                                        without the extension, '{{ext}}' is the
+                                        extension with the dot, and '{{n}}' is
+                                        the number from 1, which is required.
+                                        The default is '{{name}} ({{n}}){{ext}}'
+        --dest-dir <DIR>                Resolve destinations relative to DIR,
+                                        which is opened only once. This
+                                        implies '--resolve=beneath' unless
+                                        given otherwise
+        --expect-dev <DEV>              Like '--expect-ino' but for the device
+                                        number
+        --expect-ino <INO>              Right before renaming the only source,
+                                        open it with O_PATH and check its inode
+                                        number by statx(2). The operation fails
+                                        on a mismatch, reporting the inode
+                                        found, since the file may be replaced
+                                        after the renaming was planned
+        --expect-mtime <MTIME>          Like '--expect-ino' but for the
+                                        modification time, which is
+                                        'SEC[.FRACTION]', eg. the output of
+                                        `stat -c %.9Y`
+        --from-file <FILE>              Read operands from FILE, or stdin if
+                                        FILE is '-', instead of the command
+                                        line. Operands are terminated by NUL,
+                                        eg. output of `find -print0`. They are
+                                        source and destination pairs, or
+                                        sources only if '--target-directory'
+                                        is given
+        --journal <FILE>                Append each completed operation to
+                                        FILE, flushing it to the disk, so that
+                                        they can be reverted with '--undo'
+        --keep-old <SUFFIX>             Keep the old tree replaced by
+                                        '--replace-dir' by renaming it to OLD
+                                        with SUFFIX appended, instead of
+                                        removing it
+        --on-conflict <POLICY>          What to do if a destination exists.
+                                        'fail' (default) raises an error,
+                                        'skip' is '--no-clobber', 'overwrite'
+                                        is '--force', and 'rename' renames to
+                                        the first unused name by
+                                        '--conflict-format' instead, without
+                                        overwriting anything. The chosen name
+                                        is printed by '--verbose' and
+                                        '--output=json'
+        --output <FORMAT>               Print the outcome of each operation to
+                                        stdout in FORMAT, which is 'text'
+                                        (default) to print nothing, or 'json'
+                                        for one JSON object per line, followed
+                                        by a summary object. Non-UTF-8 paths
+                                        are also given in hex, eg. 'src_hex'
+        --rename <EXPRESSION>           Rename sources in place by applying a
+                                        sed-like 's/PATTERN/REPLACEMENT/FLAGS'
+                                        expression to their base names, eg.
+                                        's/IMG_(\\d+)/photo-$1/'. Supported
+                                        flags are 'g' and 'i'. Duplicated
+                                        destinations are rejected before
+                                        renaming anything
+        --resolve <FLAGS>               Open parent directories of sources and
+                                        destinations by openat2(2) with
+                                        RESOLVE_* FLAGS, and rename relative
+                                        to them, so symlinks swapped into
+                                        parents cannot redirect renames. FLAGS
+                                        is a comma-separated list of 'none',
+                                        'beneath', 'in-root', 'no-magiclinks',
+                                        'no-symlinks' and 'no-xdev'
+        --src-dir <DIR>                 Like '--dest-dir' but for sources
+    -S, --suffix <SUFFIX>               Override the suffix of simple backups,
+                                        which is '~' or $SIMPLE_BACKUP_SUFFIX
+                                        by default. This implies '--backup'
     -t, --target-directory <DIRECTORY>  Move all files into this directory
+        --undo <JOURNAL>                Revert operations recorded in JOURNAL
+                                        in reverse order. Files are moved back
+                                        without replacing anything. Entries
+                                        which cannot be reverted since the
+                                        paths have changed are reported
+        --update[=<UPDATE>]             Replace existing destinations only as
+                                        UPDATE allows, which is 'all', 'none'
+                                        to skip them, or 'older' (default) to
+                                        replace only those with modification
+                                        times older than their sources. They
+                                        are compared right before renaming, so
+                                        a destination modified in between may
+                                        still be replaced
+
+EXIT STATUS:
+    0   All operations succeeded, or were declined at the prompt
+    1   Some operations failed, or the journal cannot be written, or a
+        directory cannot be synced by '--sync'
+    2   Invalid arguments, or conflicting operations like duplicated
+        destinations
+    3   Operations failed and none of them remains done, eg. they are rolled
+        back by '--atomic-batch', or nothing can be started, eg. the journal
+        cannot be opened
+    4   All failed operations failed with EXDEV since they would cross mounts.
+        Copying them instead, eg. by cp(1), may work
+    5   All operations were skipped by '--no-clobber' or '--update'
 
 Copyright (C) 2021-2023 Oxalica <oxalicc@pm.me>
 This program is free software: you can redistribute it and/or modify it under
@@ -67,6 +317,8 @@ FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
         Self::parse_args(std::env::args_os().skip(1))
     }
 
+    // Validations are easier to follow when kept together in one place.
+    #[allow(clippy::too_many_lines)]
     fn parse_args<I: IntoIterator<Item = S>, S: Into<OsString>>(args: I) -> Result<Self> {
         let mut raw_args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
         let tail_positionals = match raw_args.iter().position(|s| s == "--") {
@@ -78,6 +330,9 @@ FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
             }
         };
 
+        let backup = take_optional_value(&mut raw_args, "--backup");
+        let update = take_optional_value(&mut raw_args, "--update");
+
         let mut args = Arguments::from_vec(raw_args);
 
         if args.contains(["-h", "--help"]) {
@@ -95,22 +350,148 @@ FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
             no_clobber: args.contains(["-n", "--no-clobber"]),
             interactive: args.contains(["-i", "--interactive"]),
             verbose: args.contains(["-v", "--verbose"]),
+            dry_run: args.contains(["-N", "--dry-run"]),
+            json: false,
+            atomic_batch: args.contains("--atomic-batch"),
+            exchange: args.contains("--exchange"),
+            whiteout: args.contains("--whiteout"),
+            replace_dir: args.contains("--replace-dir"),
+            symlink: args.contains("--symlink"),
+            write_atomic: None,
+            journal: args.opt_value_from_os_str("--journal", parse_path)?,
+            undo: args.opt_value_from_os_str("--undo", parse_path)?,
+            reorder: None,
+            edit: None,
+            backup: None,
+            suffix: args.opt_value_from_os_str(["-S", "--suffix"], parse_os_string)?,
+            update: None,
+            unique: None,
+            keep_old: args.opt_value_from_os_str("--keep-old", parse_os_string)?,
+            by_handle: args.opt_value_from_os_str("--by-handle", parse_path)?,
+            handle_sources: Vec::new(),
+            expect: HashMap::new(),
+            src_dir: args.opt_value_from_os_str("--src-dir", parse_path)?,
+            dest_dir: args.opt_value_from_os_str("--dest-dir", parse_path)?,
+            resolve: args.opt_value_from_fn("--resolve", parse_resolve_flags)?,
+            no_follow_parent_symlinks: args.contains("--no-follow-parent-symlinks"),
+            sync: args.contains("--sync"),
             operations: Vec::new(),
         };
-        let target_directory = args
-            .opt_value_from_os_str::<_, PathBuf, String>(["-t", "--target-directory"], |s| {
-                Ok(s.to_os_string().into())
-            })?;
+        let short_backup = args.contains("-b");
+        this.backup = match backup {
+            Some(Some(control)) => BackupControl::parse(&control.to_string_lossy())?,
+            Some(None) => default_backup_control()?,
+            None if short_backup || this.suffix.is_some() => default_backup_control()?,
+            None => None,
+        };
+        let short_update = args.contains("-u");
+        this.update = match update {
+            Some(Some(update)) => {
+                Some(parse_update(&update.to_string_lossy()).map_err(|err| anyhow!(err))?)
+            }
+            Some(None) => Some(Update::default()),
+            None if short_update => Some(Update::default()),
+            None => None,
+        };
+        let on_conflict = args.opt_value_from_fn("--on-conflict", parse_conflict_policy)?;
+        let conflict_format = args.opt_value_from_str::<_, NameFormat>("--conflict-format")?;
+        let json = args.contains("--json");
+        let output = args.opt_value_from_fn("--output", parse_output_format)?;
+        let edit = args.contains("--edit");
+        let reorder = args.contains("--reorder");
+        let break_cycles = args.opt_value_from_fn("--break-cycles", parse_cycle_strategy)?;
+        let target_directory =
+            args.opt_value_from_os_str(["-t", "--target-directory"], parse_path)?;
         let no_target_directory = args.contains(["-T", "--no-target-directory"]);
+        let from_file = args.opt_value_from_os_str("--from-file", parse_path)?;
+        let write_atomic = args.contains("--write-atomic");
+        let rename = args.opt_value_from_str::<_, Substitution>("--rename")?;
+        let expected = Expected {
+            dev: args.opt_value_from_str("--expect-dev")?,
+            ino: args.opt_value_from_str("--expect-ino")?,
+            mtime: args.opt_value_from_str("--expect-mtime")?,
+        };
+        let batch_expect = args.contains("--batch-expect");
 
-        ensure!(
-            !this.force || !this.no_clobber,
-            "Cannot use '--force' and '--no-clobber' together"
-        );
-        ensure!(
-            target_directory.is_none() || !no_target_directory,
-            "Cannot use '--no-target-directory' and '--target-directory' together"
-        );
+        // Names of all given options, to be checked against `OPTION_RULES`.
+        let given = [
+            (this.force, "--force"),
+            (this.no_clobber, "--no-clobber"),
+            (this.interactive, "--interactive"),
+            (this.verbose, "--verbose"),
+            (this.dry_run, "--dry-run"),
+            (json, "--json"),
+            (output.is_some(), "--output"),
+            (json || output == Some(true), "--output=json"),
+            (this.atomic_batch, "--atomic-batch"),
+            (this.exchange, "--exchange"),
+            (this.whiteout, "--whiteout"),
+            (this.replace_dir, "--replace-dir"),
+            (this.symlink, "--symlink"),
+            (write_atomic, "--write-atomic"),
+            (this.journal.is_some(), "--journal"),
+            (this.undo.is_some(), "--undo"),
+            (reorder, "--reorder"),
+            (break_cycles.is_some(), "--break-cycles"),
+            (edit, "--edit"),
+            (rename.is_some(), "--rename"),
+            (this.backup.is_some(), "--backup"),
+            (this.update.is_some(), "--update"),
+            (on_conflict.is_some(), "--on-conflict"),
+            (
+                on_conflict == Some(ConflictPolicy::Skip),
+                "--on-conflict=skip",
+            ),
+            (
+                on_conflict == Some(ConflictPolicy::Rename),
+                "--on-conflict=rename",
+            ),
+            (conflict_format.is_some(), "--conflict-format"),
+            (this.keep_old.is_some(), "--keep-old"),
+            (this.by_handle.is_some(), "--by-handle"),
+            (expected.dev.is_some(), "--expect-dev"),
+            (expected.ino.is_some(), "--expect-ino"),
+            (expected.mtime.is_some(), "--expect-mtime"),
+            (batch_expect, "--batch-expect"),
+            (this.src_dir.is_some(), "--src-dir"),
+            (this.dest_dir.is_some(), "--dest-dir"),
+            (this.resolve.is_some(), "--resolve"),
+            (
+                this.no_follow_parent_symlinks,
+                "--no-follow-parent-symlinks",
+            ),
+            (this.sync, "--sync"),
+            (target_directory.is_some(), "--target-directory"),
+            (no_target_directory, "--no-target-directory"),
+            (from_file.is_some(), "--from-file"),
+        ]
+        .into_iter()
+        .filter_map(|(given, name)| given.then_some(name))
+        .collect::<Vec<_>>();
+        check_option_rules(&given)?;
+
+        match on_conflict {
+            None | Some(ConflictPolicy::Fail) => {}
+            Some(ConflictPolicy::Skip) => this.no_clobber = true,
+            Some(ConflictPolicy::Overwrite) => this.force = true,
+            Some(ConflictPolicy::Rename) => this.unique = Some(conflict_format.unwrap_or_default()),
+        }
+        this.json = json || output == Some(true);
+        if this.no_follow_parent_symlinks {
+            this.resolve = Some(ResolveFlags::empty());
+        } else if this.src_dir.is_some() || this.dest_dir.is_some() {
+            this.resolve.get_or_insert(ResolveFlags::BENEATH);
+        }
+        if reorder {
+            this.reorder = Some(break_cycles.unwrap_or_default());
+        }
+        let expect_option = [
+            (expected.dev.is_some(), "--expect-dev"),
+            (expected.ino.is_some(), "--expect-ino"),
+            (expected.mtime.is_some(), "--expect-mtime"),
+        ]
+        .into_iter()
+        .find_map(|(given, name)| given.then_some(name));
 
         let mut positionals = args
             .finish()
@@ -119,25 +500,86 @@ FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
             .map(Into::into)
             .collect::<Vec<PathBuf>>();
 
-        if no_target_directory {
+        if this.undo.is_some() {
+            ensure!(
+                positionals.is_empty(),
+                "Expect no operand when using '--undo'"
+            );
+        } else if this.replace_dir {
+            let [new, old]: [_; 2] = positionals
+                .try_into()
+                .map_err(|_| anyhow!("Expect exact 2 operands when using '--replace-dir'"))?;
+            this.operations.push((new, old));
+        } else if write_atomic {
+            let [dest]: [_; 1] = positionals
+                .try_into()
+                .map_err(|_| anyhow!("Expect exact 1 operand when using '--write-atomic'"))?;
+            this.write_atomic = Some(dest);
+        } else if this.symlink {
+            let [target, link]: [_; 2] = positionals
+                .try_into()
+                .map_err(|_| anyhow!("Expect exact 2 operands when using '--symlink'"))?;
+            this.operations.push((target, link));
+        } else if edit {
+            this.edit = Some(positionals);
+        } else if let Some(subst) = rename {
+            if let Some(from_file) = from_file {
+                ensure!(
+                    positionals.is_empty(),
+                    "Cannot use '--from-file' together with operands on the command line"
+                );
+                positionals = read_operands(&from_file)?;
+            }
+            ensure!(!positionals.is_empty(), "Missing file operand");
+            this.push_substituted(positionals, &subst)?;
+        } else if let Some(from_file) = from_file {
+            ensure!(
+                positionals.is_empty(),
+                "Cannot use '--from-file' together with operands on the command line"
+            );
+            let records = read_operands(&from_file)?;
+            if let Some(target_dir) = target_directory {
+                let mut records = records.into_iter();
+                let mut srcs = Vec::new();
+                while let Some(src) = records.next() {
+                    let expected = if batch_expect {
+                        parse_batch_expected(records.next(), &src)?
+                    } else {
+                        Expected::default()
+                    };
+                    srcs.push((src, expected));
+                }
+                this.push_move_to_dir(srcs, &target_dir)?;
+            } else {
+                this.push_pairs(records, batch_expect)?;
+            }
+        } else if this.exchange {
+            let [src, dest]: [_; 2] = positionals
+                .try_into()
+                .map_err(|_| anyhow!("Expect exact 2 operands when using '--exchange'"))?;
+            this.operations.push((src, dest));
+        } else if no_target_directory {
             let [src, dest]: [_; 2] = positionals.try_into().map_err(|_| {
                 anyhow!("Expect exact 2 operands when using '--no-target-directory'")
             })?;
-            this.operations.push((src, dest));
+            this.push_source(src, expected, Dest::Path(dest))?;
         } else if let Some(target_dir) = target_directory {
             ensure!(!positionals.is_empty(), "Missing file operand");
-            this.push_move_to_dir(positionals, &target_dir)?;
+            let srcs = with_expected(positionals, expected, expect_option)?;
+            this.push_move_to_dir(srcs, &target_dir)?;
         } else {
+            let dest_base = this.dest_dir.clone().unwrap_or_else(|| ".".into());
             match positionals.len() {
                 0 => bail!("Missing file operand"),
                 1 => bail!("Missing destination operand"),
-                2 if !positionals[1].is_dir() => {
+                2 if !dest_base.join(&positionals[1]).is_dir() => {
                     let [src, dest]: [_; 2] = positionals.try_into().unwrap();
-                    this.operations.push((src, dest));
+                    this.push_source(src, expected, Dest::Path(dest))?;
                 }
                 _ => {
                     let target_dir = positionals.pop().unwrap();
-                    this.push_move_to_dir(positionals, &target_dir)?;
+                    let srcs = with_expected(positionals, expected, expect_option)?;
+                    this.push_move_to_dir(srcs, &target_dir)?;
                 }
             }
         }
@@ -145,80 +587,758 @@ FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
         Ok(this)
     }
 
-    fn push_move_to_dir(
-        &mut self,
-        srcs: impl IntoIterator<Item = PathBuf>,
-        target_dir: &Path,
-    ) -> Result<()> {
+    fn mode(&self) -> RenameMode {
+        if self.exchange {
+            RenameMode::Exchange
+        } else if self.whiteout {
+            RenameMode::Whiteout {
+                replace: self.replaces_directly(),
+            }
+        } else if self.replaces_directly() {
+            RenameMode::Replace
+        } else {
+            RenameMode::NoReplace
+        }
+    }
+
+    /// Whether destinations are replaced by the first rename, without
+    /// inspecting them before.
+    fn replaces_directly(&self) -> bool {
+        self.force && self.backup.is_none() && self.update.is_none()
+    }
+
+    /// The operations to perform, in order.
+    fn plan(&self) -> Result<Vec<RenameOp>> {
+        let mode = self.mode();
+        Ok(match self.reorder {
+            Some(strategy) => plan::plan(&self.operations, mode, strategy)?,
+            None => self
+                .operations
+                .iter()
+                .map(|(src, dest)| RenameOp::new(src, dest, mode))
+                .collect(),
+        })
+    }
+
+    /// Push an operation of `src` with `expected` of it. A source given as a
+    /// file handle by `--by-handle` is only parsed here, and located later by
+    /// [`locate_sources`].
+    fn push_source(&mut self, src: PathBuf, expected: Expected, dest: Dest) -> Result<()> {
+        if self.by_handle.is_some() {
+            let handle = src.to_string_lossy().parse::<FileHandle>()?;
+            self.handle_sources.push(HandleSource {
+                handle,
+                expected,
+                dest,
+            });
+            return Ok(());
+        }
+        ensure!(
+            matches!(dest, Dest::Path(_)) || src.file_name().is_some(),
+            "Source doesn't have base name: {}",
+            src.display(),
+        );
+        let dest = dest.of(&src);
+        self.push_expected(src, dest, expected);
+        Ok(())
+    }
+
+    /// Push an operation, and record `expected` of the source to be checked
+    /// right before renaming it.
+    fn push_expected(&mut self, src: PathBuf, dest: PathBuf, expected: Expected) {
+        if expected != Expected::default() {
+            self.expect.insert(src.clone(), expected);
+        }
+        self.operations.push((src, dest));
+    }
+
+    /// How to execute the operations, with parent directories resolved by
+    /// `dirs` if any.
+    fn batch(&self, dirs: Option<(Resolver, Resolver)>) -> Batch {
+        let on_exists = if self.no_clobber {
+            OnExists::Skip
+        } else if let Some(format) = &self.unique {
+            OnExists::Unique(format.clone())
+        } else if self.interactive {
+            OnExists::Ask
+        } else if self.force || self.update.is_some() || self.backup.is_some() {
+            // Backups alone also mean overwriting after backing up, like
+            // mv(1).
+            OnExists::Overwrite
+        } else {
+            OnExists::Fail
+        };
+        let suffix = self
+            .suffix
+            .clone()
+            .or_else(|| std::env::var_os("SIMPLE_BACKUP_SUFFIX").filter(|s| !s.is_empty()))
+            .unwrap_or_else(|| "~".into());
+        Batch {
+            on_exists,
+            update: self.update,
+            backup: self.backup.map(|control| (control, suffix)),
+            expect: self.expect.clone(),
+            dirs,
+            atomic: self.atomic_batch,
+            sync: self.sync,
+        }
+    }
+
+    /// Push pairs of sources and destinations, each followed by its
+    /// expectation if `batch_expect` is set.
+    fn push_pairs(&mut self, records: Vec<PathBuf>, batch_expect: bool) -> Result<()> {
+        let mut records = records.into_iter();
+        while let Some(src) = records.next() {
+            let dest = records.next().ok_or_else(|| {
+                anyhow!(
+                    "Missing destination operand for {} in '--from-file'",
+                    src.display()
+                )
+            })?;
+            let expected = if batch_expect {
+                parse_batch_expected(records.next(), &src)?
+            } else {
+                Expected::default()
+            };
+            self.push_source(src, expected, Dest::Path(dest))?;
+        }
+        Ok(())
+    }
+
+    /// Rename sources in place by substituting their base names. Sources whose
+    /// names are unchanged are ignored.
+    fn push_substituted(&mut self, srcs: Vec<PathBuf>, subst: &Substitution) -> Result<()> {
+        let mut dests = HashMap::new();
         for src in srcs {
             let base = src
                 .file_name()
                 .ok_or_else(|| anyhow!("Source doesn't have base name: {}", src.display()))?;
-            let dest = target_dir.join(base);
+            let Some(new_base) = subst.apply(base).filter(|new_base| new_base != base) else {
+                continue;
+            };
+            ensure!(
+                !new_base.is_empty() && !new_base.as_bytes().contains(&b'/'),
+                "Invalid name {new_base:?} substituted from {}",
+                src.display(),
+            );
+            let dest = src.with_file_name(new_base);
+            if let Some(prev) = dests.insert(dest.clone(), src.clone()) {
+                bail!(
+                    "Both {} and {} would be renamed to {}",
+                    prev.display(),
+                    src.display(),
+                    dest.display(),
+                );
+            }
             self.operations.push((src, dest));
         }
         Ok(())
     }
+
+    fn push_move_to_dir(
+        &mut self,
+        srcs: impl IntoIterator<Item = (PathBuf, Expected)>,
+        target_dir: &Path,
+    ) -> Result<()> {
+        for (src, expected) in srcs {
+            self.push_source(src, expected, Dest::IntoDir(target_dir.to_owned()))?;
+        }
+        Ok(())
+    }
 }
 
-fn main() {
-    let app = App::parse_env().unwrap_or_else(|err| {
-        eprintln!("rawmv: {err}");
-        process::exit(1);
-    });
+/// Where a source is moved to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Dest {
+    Path(PathBuf),
+    /// Into the directory with the base name of the source, which may only
+    /// be known after locating it.
+    IntoDir(PathBuf),
+}
 
-    let mut failed = false;
-    for (src, dest) in &app.operations {
-        let mut ret = do_rename(src, dest, app.force);
-        if !app.force && matches!(&ret, Err(err) if err.kind() == io::ErrorKind::AlreadyExists) {
-            if app.no_clobber {
-                continue;
-            } else if app.interactive {
-                eprint!("rawmv: Overwrite {src:?} -> {dest:?} ? [y/N] ");
-                let _ = io::stderr().flush();
-                let mut input = String::new();
-                let _ = io::stdin().read_line(&mut input);
-                if input.trim() == "y" {
-                    ret = do_rename(src, dest, true);
-                } else {
-                    continue;
+impl Dest {
+    fn of(&self, src: &Path) -> PathBuf {
+        match self {
+            Self::Path(dest) => dest.clone(),
+            Self::IntoDir(dir) => dir.join(src.file_name().unwrap_or_default()),
+        }
+    }
+}
+
+/// A source given as a file handle by `--by-handle`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct HandleSource {
+    handle: FileHandle,
+    expected: Expected,
+    dest: Dest,
+}
+
+/// Locate sources given as file handles by `--by-handle`, and push their
+/// operations. They are expected to be the same files when renamed, unless
+/// given otherwise. Handles which cannot be located are reported as failed
+/// operations in the returned summary.
+fn locate_sources(app: &mut App) -> Summary {
+    let mut summary = Summary::default();
+    let Some(mount) = app.by_handle.clone() else {
+        return summary;
+    };
+    let mode = app.mode();
+    for source in std::mem::take(&mut app.handle_sources) {
+        let HandleSource {
+            handle,
+            expected,
+            dest,
+        } = source;
+        match handle.locate(&mount, mode) {
+            Ok((path, located)) => {
+                let dest = dest.of(&path);
+                app.push_expected(path, dest, expected.or(located));
+            }
+            Err(err) => {
+                eprintln!("rawmv: Cannot locate file handle {handle}: {err}");
+                summary.add(Outcome::Failed, Some(&err));
+                if app.json {
+                    let (Dest::Path(dest) | Dest::IntoDir(dest)) = dest;
+                    let op = RenameOp::new(handle.to_string(), dest, mode);
+                    let report = Report {
+                        op: &op,
+                        outcome: Outcome::Failed,
+                        backup: None,
+                        error: Some(&err),
+                    };
+                    println!("{}", report::Entry(&report));
                 }
             }
         }
+    }
+    summary
+}
+
+/// The verb, its past tense, and the arrow used to describe operations.
+fn wording(mode: RenameMode) -> (&'static str, &'static str, &'static str) {
+    if mode == RenameMode::Exchange {
+        ("exchange", "Exchanged", "<->")
+    } else {
+        ("rename", "Renamed", "->")
+    }
+}
+
+/// Revert operations recorded in a journal, and return the exit status.
+fn undo(path: &Path, verbose: bool) -> Status {
+    let entries = journal::read(path).unwrap_or_else(|err| {
+        fail(
+            Status::TotalFailure,
+            format!("Cannot read journal {path:?}: {err}"),
+        )
+    });
 
-        match ret {
+    let mut summary = Summary::default();
+    for op in entries.iter().rev() {
+        let (verb, _, arrow) = wording(op.mode);
+        let (src, dest) = (&op.src, &op.dest);
+        match op.revert() {
             Ok(()) => {
-                if app.verbose {
-                    eprintln!("rawmv: Renamed {src:?} -> {dest:?}");
+                if verbose {
+                    eprintln!("rawmv: Reverted {src:?} {arrow} {dest:?}");
                 }
+                summary.add(Outcome::Renamed, None);
             }
             Err(err) => {
-                eprintln!("rawmv: Cannot rename {src:?} -> {dest:?}: {err}");
-                failed = true;
+                eprintln!("rawmv: Cannot revert {verb} {src:?} {arrow} {dest:?}: {err}");
+                summary.add(Outcome::Failed, Some(&err));
+            }
+        }
+    }
+    Status::of(&summary)
+}
+
+/// Replace a directory with a new one, and return the exit status.
+fn replace_dir(app: &App) -> Status {
+    let (new, old) = &app.operations[0];
+    let mut status = match replace::exchange_dirs(new, old) {
+        Ok(true) => {
+            if app.verbose {
+                eprintln!("rawmv: Exchanged {new:?} <-> {old:?}");
+            }
+            match replace::dispose(new, old, app.keep_old.as_deref()) {
+                Ok(kept) => {
+                    if app.verbose {
+                        match kept {
+                            Some(kept) => eprintln!("rawmv: Kept the old tree as {kept:?}"),
+                            None => eprintln!("rawmv: Removed the old tree at {new:?}"),
+                        }
+                    }
+                    Status::Success
+                }
+                Err(err) => {
+                    eprintln!("rawmv: Replaced {old:?} but the old tree is left at {new:?}: {err}");
+                    Status::PartialFailure
+                }
+            }
+        }
+        Ok(false) => {
+            if app.verbose {
+                eprintln!("rawmv: Renamed {new:?} -> {old:?}");
+            }
+            Status::Success
+        }
+        Err(err) => {
+            eprintln!("rawmv: Cannot replace {old:?} with {new:?}: {err}");
+            return Status::TotalFailure;
+        }
+    };
+
+    if app.sync {
+        let mut dir_sync = DirSync::new();
+        for path in [new, old] {
+            if let Err(err) = dir_sync.sync_parent(path, None) {
+                eprintln!("rawmv: Cannot sync the parent of {path:?}: {err}");
+                status = Status::PartialFailure;
+            }
+        }
+    }
+    status
+}
+
+/// Point a symlink to a new target, and return the exit status.
+fn symlink(app: &App) -> Status {
+    let (target, link) = &app.operations[0];
+    if let Err(err) = symlink::retarget(target, link) {
+        eprintln!("rawmv: Cannot point {link:?} to {target:?}: {err}");
+        return Status::TotalFailure;
+    }
+    if app.verbose {
+        eprintln!("rawmv: Pointed {link:?} to {target:?}");
+    }
+    if app.sync {
+        if let Err(err) = DirSync::new().sync_parent(link, None) {
+            eprintln!("rawmv: Cannot sync the parent of {link:?}: {err}");
+            return Status::PartialFailure;
+        }
+    }
+    Status::Success
+}
+
+/// Print planned operations with their predicted handling, and return the
+/// expected exit status. `summary` may already contain failures of locating
+/// sources.
+fn dry_run(batch: &Batch, ops: &[RenameOp], mut summary: Summary) -> Status {
+    for (op, (prediction, err)) in ops.iter().zip(batch.simulate(ops)) {
+        let (verb, _, arrow) = wording(op.mode);
+        let flags = match op.mode.flag_names() {
+            [] => "0".to_owned(),
+            names => names.join("|"),
+        };
+        print!("{verb} {:?} {arrow} {:?} ({flags})", op.src, op.dest);
+        let message = match prediction {
+            Prediction::Rename => None,
+            Prediction::Skip => Some("would skip"),
+            Prediction::SkipByUpdate => Some("would skip by '--update'"),
+            // Assume the user would confirm.
+            Prediction::Ask => Some("would prompt"),
+            Prediction::BackUp => Some("would back up the destination"),
+            Prediction::RenameUnique => Some("would rename to an unused name"),
+            Prediction::Replace => Some("would replace the destination"),
+            Prediction::Fail => Some("would fail"),
+        };
+        match (message, &err) {
+            (Some(message), Some(err)) => println!(": {message}: {err}"),
+            _ => println!(),
+        }
+        summary.add(prediction.outcome(), err.as_ref());
+    }
+    let status = Status::of(&summary);
+    println!("would exit with status {}", status as i32);
+    status
+}
+
+/// Print events of a batch as requested, and ask the user about existing
+/// destinations if interactive.
+struct Reporter<'a> {
+    app: &'a App,
+    prompt: Option<Prompt>,
+}
+
+impl Observer for Reporter<'_> {
+    fn ask(&mut self, op: &RenameOp) -> Decision {
+        let (src, dest) = (&op.src, &op.dest);
+        match &mut self.prompt {
+            Some(prompt) => prompt.ask(&format!("Overwrite {src:?} -> {dest:?} ?"), src, dest),
+            None => Decision::Keep,
+        }
+    }
+
+    fn backed_up(&mut self, backup: &RenameOp) {
+        if self.app.verbose {
+            eprintln!("rawmv: Backed up {:?} -> {:?}", backup.src, backup.dest);
+        }
+    }
+
+    fn finished(&mut self, report: &Report<'_>) {
+        let (verb, done, arrow) = wording(report.op.mode);
+        let (src, dest) = (&report.op.src, &report.op.dest);
+        let verbose = self.app.verbose;
+        match (report.outcome, report.error) {
+            (Outcome::Renamed, _) if verbose => {
+                eprintln!("rawmv: {done} {src:?} {arrow} {dest:?}");
+            }
+            // Nothing but '--update' skips with '--verbose'.
+            (Outcome::Skipped, _) if verbose => {
+                let reason = match self.app.update {
+                    Some(Update::Older) => "is not older",
+                    _ => "exists",
+                };
+                eprintln!("rawmv: Skipped {src:?} {arrow} {dest:?} since the destination {reason}");
+            }
+            (Outcome::Failed, Some(err)) => {
+                eprintln!("rawmv: Cannot {verb} {src:?} {arrow} {dest:?}: {err}");
+            }
+            (Outcome::RolledBack, _) if verbose => {
+                eprintln!("rawmv: Rolled back {src:?} {arrow} {dest:?}");
+            }
+            _ => {}
+        }
+        if self.app.json {
+            println!("{}", report::Entry(report));
+        }
+    }
+
+    fn aborted(&mut self) {
+        eprintln!("rawmv: Nothing is renamed since the batch cannot complete");
+    }
+
+    fn record_failed(&mut self, op: &RenameOp, err: &io::Error) {
+        let (_, _, arrow) = wording(op.mode);
+        eprintln!(
+            "rawmv: Cannot record {:?} {arrow} {:?} in journal: {err}",
+            op.src, op.dest,
+        );
+    }
+
+    fn restore_failed(&mut self, backup: &RenameOp, err: &rawmv::Error) {
+        let (src, backup) = (&backup.src, &backup.dest);
+        eprintln!("rawmv: Cannot restore backup {backup:?} -> {src:?}: {err}");
+    }
+
+    fn roll_back_failed(&mut self, op: &RenameOp, err: &rawmv::Error) {
+        let (verb, _, arrow) = wording(op.mode);
+        let (src, dest) = (&op.src, &op.dest);
+        eprintln!("rawmv: Cannot roll back {verb} {src:?} {arrow} {dest:?}: {err}");
+    }
+
+    fn synced(&mut self, dir: &Path, error: Option<&io::Error>) {
+        if let Some(err) = error {
+            eprintln!("rawmv: Cannot sync directory {dir:?}: {err}");
+        }
+        if self.app.json {
+            println!("{}", report::SyncEntry { dir, error });
+        }
+    }
+}
+
+/// Parse the comma-separated flags of `--resolve`.
+fn parse_resolve_flags(s: &str) -> Result<ResolveFlags, String> {
+    let mut flags = ResolveFlags::empty();
+    for name in s.split(',') {
+        flags |= match name {
+            "none" => ResolveFlags::empty(),
+            "beneath" => ResolveFlags::BENEATH,
+            "in-root" => ResolveFlags::IN_ROOT,
+            "no-magiclinks" => ResolveFlags::NO_MAGICLINKS,
+            "no-symlinks" => ResolveFlags::NO_SYMLINKS,
+            "no-xdev" => ResolveFlags::NO_XDEV,
+            _ => {
+                return Err(format!(
+                    "Unknown flag '{name}', expect 'none', 'beneath', 'in-root', \
+                     'no-magiclinks', 'no-symlinks' or 'no-xdev'"
+                ))
             }
+        };
+    }
+    Ok(flags)
+}
+
+/// What to do if a destination exists, as given by `--on-conflict`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConflictPolicy {
+    Fail,
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+fn parse_conflict_policy(s: &str) -> Result<ConflictPolicy, String> {
+    match s {
+        "fail" => Ok(ConflictPolicy::Fail),
+        "skip" => Ok(ConflictPolicy::Skip),
+        "overwrite" => Ok(ConflictPolicy::Overwrite),
+        "rename" => Ok(ConflictPolicy::Rename),
+        _ => Err("Expect 'fail', 'skip', 'overwrite' or 'rename'".into()),
+    }
+}
+
+/// Parse the format of `--output`, and return whether it is JSON.
+fn parse_output_format(s: &str) -> Result<bool, String> {
+    match s {
+        "text" => Ok(false),
+        "json" => Ok(true),
+        _ => Err("Expect 'text' or 'json'".into()),
+    }
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn parse_path(s: &OsStr) -> Result<PathBuf, String> {
+    Ok(s.into())
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn parse_os_string(s: &OsStr) -> Result<OsString, String> {
+    Ok(s.to_owned())
+}
+
+/// Remove all occurrences of an option with an optional value, ie.
+/// `--name` or `--name=VALUE`, and return the value of the last one. The
+/// outer `None` means the option is absent, and the inner one means it has no
+/// value.
+#[allow(clippy::option_option)]
+fn take_optional_value(raw_args: &mut Vec<OsString>, name: &str) -> Option<Option<OsString>> {
+    let mut last = None;
+    raw_args.retain(|arg| {
+        let arg = arg.as_bytes();
+        let Some(tail) = arg.strip_prefix(name.as_bytes()) else {
+            return true;
+        };
+        if tail.is_empty() {
+            last = Some(None);
+        } else if let Some(value) = tail.strip_prefix(b"=") {
+            last = Some(Some(OsStr::from_bytes(value).to_owned()));
+        } else {
+            return true;
         }
+        false
+    });
+    last
+}
+
+/// The backup control from `VERSION_CONTROL`, or 'existing' by default.
+fn default_backup_control() -> Result<Option<BackupControl>> {
+    match std::env::var("VERSION_CONTROL") {
+        Ok(control) if !control.is_empty() => Ok(BackupControl::parse(&control)?),
+        _ => Ok(Some(BackupControl::default())),
+    }
+}
+
+fn parse_update(s: &str) -> Result<Update, String> {
+    match s {
+        "all" => Ok(Update::All),
+        "none" => Ok(Update::None),
+        "older" => Ok(Update::Older),
+        _ => Err(format!(
+            "Invalid update type '{s}', expect 'all', 'none' or 'older'"
+        )),
+    }
+}
+
+fn parse_cycle_strategy(s: &str) -> Result<CycleStrategy, String> {
+    match s {
+        "exchange" => Ok(CycleStrategy::Exchange),
+        "temp" => Ok(CycleStrategy::TempName),
+        _ => Err("Expect 'exchange' or 'temp'".into()),
     }
+}
 
-    if failed {
-        process::exit(1);
+/// Attach the expectation given on the command line to the only source.
+fn with_expected(
+    srcs: Vec<PathBuf>,
+    expected: Expected,
+    expect_option: Option<&str>,
+) -> Result<Vec<(PathBuf, Expected)>> {
+    if let Some(name) = expect_option {
+        ensure!(srcs.len() == 1, "Expect exact 1 source when using '{name}'");
     }
+    Ok(srcs.into_iter().map(|src| (src, expected)).collect())
 }
 
-fn do_rename(src: &Path, dest: &Path, overwrite: bool) -> io::Result<()> {
-    use rustix::fs;
+/// Parse the expectation following `src` in '--from-file' with
+/// '--batch-expect'.
+fn parse_batch_expected(record: Option<PathBuf>, src: &Path) -> Result<Expected> {
+    let record = record
+        .ok_or_else(|| anyhow!("Missing expectation for {} in '--from-file'", src.display()))?;
+    Ok(record.to_string_lossy().parse()?)
+}
 
-    let flags = if overwrite {
-        fs::RenameFlags::empty()
+fn read_operands(from_file: &Path) -> Result<Vec<PathBuf>> {
+    if from_file == Path::new("-") {
+        read_nul_records(io::stdin().lock())
     } else {
-        fs::RenameFlags::NOREPLACE
+        File::open(from_file).and_then(read_nul_records)
+    }
+    .with_context(|| format!("Cannot read operands from {}", from_file.display()))
+}
+
+/// Read NUL-terminated paths. The terminator of the last one is optional.
+fn read_nul_records(reader: impl Read) -> io::Result<Vec<PathBuf>> {
+    let mut records = Vec::new();
+    for record in BufReader::new(reader).split(b'\0') {
+        let record = record?;
+        if record.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected empty path",
+            ));
+        }
+        records.push(PathBuf::from(OsString::from_vec(record)));
+    }
+    Ok(records)
+}
+
+/// The exit status of the process, as documented in the help.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Success = 0,
+    PartialFailure = 1,
+    Usage = 2,
+    TotalFailure = 3,
+    CrossDevice = 4,
+    AllSkipped = 5,
+}
+
+impl Status {
+    /// Classify the result of all operations.
+    fn of(summary: &Summary) -> Self {
+        if summary.journal_failed {
+            // Something may have been moved without being recorded.
+            Self::PartialFailure
+        } else if summary.failed != 0 {
+            if summary.cross_device == summary.failed {
+                Self::CrossDevice
+            } else if summary.renamed == 0 {
+                Self::TotalFailure
+            } else {
+                Self::PartialFailure
+            }
+        } else if summary.sync_failed != 0 {
+            Self::PartialFailure
+        } else if summary.skipped != 0 && summary.skipped == summary.total() {
+            Self::AllSkipped
+        } else {
+            Self::Success
+        }
+    }
+
+    fn exit(self) -> ! {
+        process::exit(self as i32)
+    }
+}
+
+/// Print an error and exit with `status`.
+fn fail(status: Status, err: impl fmt::Display) -> ! {
+    eprintln!("rawmv: {err}");
+    status.exit()
+}
+
+fn main() {
+    let mut app = App::parse_env().unwrap_or_else(|err| fail(Status::Usage, err));
+
+    if let Some(journal) = &app.undo {
+        undo(journal, app.verbose).exit();
+    }
+    if app.replace_dir {
+        replace_dir(&app).exit();
+    }
+    if app.symlink {
+        symlink(&app).exit();
+    }
+
+    let mut journal = app.journal.as_ref().map(|path| {
+        Journal::open(path).unwrap_or_else(|err| {
+            fail(
+                Status::TotalFailure,
+                format!("Cannot open journal {path:?}: {err}"),
+            )
+        })
+    });
+
+    if let Some(paths) = &app.edit {
+        let editor = ["VISUAL", "EDITOR"]
+            .into_iter()
+            .find_map(|var| std::env::var(var).ok().filter(|s| !s.is_empty()))
+            .unwrap_or_else(|| "vi".into());
+        app.operations = edit::list_paths(paths)
+            .and_then(|paths| edit::edit(&paths, &editor))
+            .unwrap_or_else(|err| fail(Status::TotalFailure, err));
+    }
+
+    let written = app.write_atomic.clone().map(|dest| {
+        let temp =
+            write::write_temp(&mut io::stdin().lock(), &dest, app.sync).unwrap_or_else(|err| {
+                fail(
+                    Status::TotalFailure,
+                    format!("Cannot write a temporary file for {dest:?}: {err}"),
+                )
+            });
+        app.operations.push((temp.clone(), dest));
+        temp
+    });
+
+    let located = locate_sources(&mut app);
+    let ops = app.plan().unwrap_or_else(|err| fail(Status::Usage, err));
+    if app.dry_run {
+        dry_run(&app.batch(None), &ops, located).exit();
+    }
+
+    let dirs = app.resolve.map(|flags| {
+        let open = |dir: &Option<PathBuf>| {
+            let resolver = match dir {
+                Some(dir) => Resolver::open(dir, flags).unwrap_or_else(|err| {
+                    fail(
+                        Status::TotalFailure,
+                        format!("Cannot open directory {dir:?}: {err}"),
+                    )
+                }),
+                None => Resolver::new(flags),
+            };
+            if app.no_follow_parent_symlinks {
+                resolver.walking()
+            } else {
+                resolver
+            }
+        };
+        (open(&app.src_dir), open(&app.dest_dir))
+    });
+
+    let mut summary = located;
+    let mut reporter = Reporter {
+        app: &app,
+        prompt: app.interactive.then(Prompt::new),
     };
-    fs::renameat_with(fs::CWD, src, fs::CWD, dest, flags)?;
-    Ok(())
+    app.batch(dirs)
+        .run(ops, journal.as_mut(), &mut reporter, &mut summary);
+    // Do not leave the written file if it is not renamed into place.
+    if let Some(temp) = written.filter(|_| summary.renamed == 0) {
+        let _ = std::fs::remove_file(temp);
+    }
+    if app.json {
+        println!("{}", report::SummaryEntry(&summary));
+    }
+    Status::of(&summary).exit();
 }
 
 #[cfg(test)]
 mod tests {
-    use super::App;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use rawmv::backup::BackupControl;
+    use rawmv::expect::Expected;
+    use rawmv::plan::CycleStrategy;
+    use rawmv::unique::NameFormat;
+    use rawmv::update::Update;
+    use rustix::fs::ResolveFlags;
+
+    use super::{dry_run, read_nul_records, App, Dest, HandleSource, Status, Summary};
+    use crate::testutil::ScratchDir;
 
     fn parse(args: &[&str]) -> Result<App, String> {
         App::parse_args(args.iter()).map_err(|e| e.to_string())
@@ -331,6 +1451,704 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_exchange() {
+        assert_eq!(
+            parse(&["--exchange", "foo", "/"]).unwrap(),
+            App {
+                exchange: true,
+                operations: vec![("foo".into(), "/".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--exchange", "foo"]).unwrap_err(),
+            "Expect exact 2 operands when using '--exchange'",
+        );
+        assert_eq!(
+            parse(&["--exchange", "foo", "bar", "/"]).unwrap_err(),
+            "Expect exact 2 operands when using '--exchange'",
+        );
+        assert_eq!(
+            parse(&["--exchange", "-n", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--exchange' and '--no-clobber' together",
+        );
+        assert_eq!(
+            parse(&["--exchange", "-t", "/", "foo"]).unwrap_err(),
+            "Cannot use '--exchange' and '--target-directory' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_whiteout() {
+        assert_eq!(
+            parse(&["--whiteout", "foo", "/"]).unwrap(),
+            App {
+                whiteout: true,
+                operations: vec![("foo".into(), "/foo".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--whiteout", "--exchange", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--exchange' and '--whiteout' together",
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let status = |renamed, skipped, failed, cross_device| {
+            Status::of(&Summary {
+                renamed,
+                skipped,
+                prompt_declined: 0,
+                failed,
+                cross_device,
+                rolled_back: 0,
+                sync_failed: 0,
+                journal_failed: false,
+            })
+        };
+        assert_eq!(status(0, 0, 0, 0), Status::Success);
+        assert_eq!(status(1, 1, 0, 0), Status::Success);
+        assert_eq!(status(0, 2, 0, 0), Status::AllSkipped);
+        assert_eq!(status(1, 0, 1, 0), Status::PartialFailure);
+        assert_eq!(status(0, 0, 2, 0), Status::TotalFailure);
+        assert_eq!(status(1, 0, 2, 2), Status::CrossDevice);
+        assert_eq!(status(0, 0, 2, 1), Status::TotalFailure);
+    }
+
+    #[test]
+    fn test_read_nul_records() {
+        let read = |s: &[u8]| read_nul_records(s).map_err(|e| e.to_string());
+        assert_eq!(read(b"").unwrap(), Vec::<PathBuf>::new());
+        assert_eq!(
+            read(b"foo\0bar baz\0").unwrap(),
+            vec![PathBuf::from("foo"), PathBuf::from("bar baz")],
+        );
+        assert_eq!(
+            read(b"foo\nbar\0baz").unwrap(),
+            vec![PathBuf::from("foo\nbar"), PathBuf::from("baz")],
+        );
+        assert_eq!(read(b"foo\0\0bar").unwrap_err(), "Unexpected empty path");
+    }
+
+    #[test]
+    fn test_parse_from_file() {
+        assert_eq!(
+            parse(&["--from-file", "/dev/null"]).unwrap(),
+            App::default()
+        );
+        assert_eq!(
+            parse(&["-t", "/", "--from-file", "/dev/null"]).unwrap(),
+            App::default()
+        );
+        assert_eq!(
+            parse(&["--from-file", "/dev/null", "foo"]).unwrap_err(),
+            "Cannot use '--from-file' together with operands on the command line",
+        );
+        assert_eq!(
+            parse(&["--from-file", "/dev/null", "-T"]).unwrap_err(),
+            "Cannot use '--no-target-directory' and '--from-file' together",
+        );
+        assert_eq!(
+            parse(&["--from-file", "/non/existing/file"]).unwrap_err(),
+            "Cannot read operands from /non/existing/file",
+        );
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        assert_eq!(
+            parse(&["-N", "foo", "/"]).unwrap(),
+            App {
+                dry_run: true,
+                operations: vec![("foo".into(), "/foo".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["-N", "--journal", "j", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--dry-run' and '--journal' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let expect = App {
+            json: true,
+            operations: vec![("foo".into(), "/foo".into())],
+            ..App::default()
+        };
+        assert_eq!(parse(&["--json", "foo", "/"]).unwrap(), expect);
+        assert_eq!(parse(&["--output=json", "foo", "/"]).unwrap(), expect);
+        assert_eq!(parse(&["--output", "json", "foo", "/"]).unwrap(), expect);
+        assert!(!parse(&["--output=text", "foo", "/"]).unwrap().json);
+        assert!(parse(&["--output=yaml", "foo", "/"]).is_err());
+        assert_eq!(
+            parse(&["--json", "-N", "foo", "/"]).unwrap_err(),
+            "Cannot use '--output=json' and '--dry-run' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_atomic_batch() {
+        assert_eq!(
+            parse(&["--atomic-batch", "-f", "foo", "bar", "/"]).unwrap(),
+            App {
+                force: true,
+                atomic_batch: true,
+                operations: vec![("foo".into(), "/foo".into()), ("bar".into(), "/bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--atomic-batch", "-n", "foo", "/"]).unwrap_err(),
+            "Cannot use '--atomic-batch' and '--no-clobber' together",
+        );
+        assert_eq!(
+            parse(&["--atomic-batch", "--journal", "log", "foo", "/"]).unwrap_err(),
+            "Cannot use '--atomic-batch' and '--journal' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_update() {
+        let app = App {
+            update: Some(Update::Older),
+            operations: vec![("foo".into(), "bar".into())],
+            ..App::default()
+        };
+        assert_eq!(parse(&["-uT", "foo", "bar"]).unwrap(), app);
+        assert_eq!(parse(&["--update", "-T", "foo", "bar"]).unwrap(), app);
+        assert_eq!(
+            parse(&["--update=none", "-T", "foo", "bar"]).unwrap(),
+            App {
+                update: Some(Update::None),
+                ..app.clone()
+            }
+        );
+        // The long form wins over '-u', which is still consumed.
+        assert_eq!(
+            parse(&["--update=none", "-u", "-T", "foo", "bar"]).unwrap(),
+            App {
+                update: Some(Update::None),
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&["-f", "--update=all", "-T", "foo", "bar"]).unwrap(),
+            App {
+                force: true,
+                update: Some(Update::All),
+                ..app
+            }
+        );
+        assert_eq!(
+            parse(&["--update=newer", "-T", "foo", "bar"]).unwrap_err(),
+            "Invalid update type 'newer', expect 'all', 'none' or 'older'",
+        );
+        assert_eq!(
+            parse(&["-nu", "-T", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--update' and '--no-clobber' together",
+        );
+        assert_eq!(
+            parse(&["--atomic-batch", "-u", "-T", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--atomic-batch' and '--update' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_on_conflict() {
+        let app = App {
+            operations: vec![("foo".into(), "bar".into())],
+            ..App::default()
+        };
+        assert_eq!(
+            parse(&["--on-conflict=fail", "-T", "foo", "bar"]).unwrap(),
+            app
+        );
+        assert_eq!(
+            parse(&["--on-conflict=skip", "-T", "foo", "bar"]).unwrap(),
+            App {
+                no_clobber: true,
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&["--on-conflict", "rename", "-T", "foo", "bar"]).unwrap(),
+            App {
+                unique: Some(NameFormat::default()),
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&[
+                "--on-conflict=rename",
+                "--conflict-format={name}.{n}{ext}",
+                "-T",
+                "foo",
+                "bar"
+            ])
+            .unwrap(),
+            App {
+                unique: Some("{name}.{n}{ext}".parse().unwrap()),
+                ..app
+            }
+        );
+        assert_eq!(
+            parse(&["--on-conflict=overwrite", "-f", "-T", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--force' and '--on-conflict' together",
+        );
+        assert_eq!(
+            parse(&["--conflict-format={n}", "-T", "foo", "bar"]).unwrap_err(),
+            "'--conflict-format' can only be used together with '--on-conflict=rename'",
+        );
+        assert_eq!(
+            parse(&["--on-conflict=rename", "-b", "-T", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--backup' and '--on-conflict=rename' together",
+        );
+        assert_eq!(
+            parse(&["--on-conflict=skip", "--exchange", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--exchange' and '--on-conflict=skip' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_replace_dir() {
+        assert_eq!(
+            parse(&["--replace-dir", "--keep-old", ".old", "new", "old"]).unwrap(),
+            App {
+                replace_dir: true,
+                keep_old: Some(".old".into()),
+                operations: vec![("new".into(), "old".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--replace-dir", "new"]).unwrap_err(),
+            "Expect exact 2 operands when using '--replace-dir'",
+        );
+        assert_eq!(
+            parse(&["--replace-dir", "-f", "new", "old"]).unwrap_err(),
+            "Only '--verbose', '--sync' and '--keep-old' can be used together with '--replace-dir'",
+        );
+        assert_eq!(
+            parse(&["--keep-old", ".old", "new", "old"]).unwrap_err(),
+            "'--keep-old' can only be used together with '--replace-dir'",
+        );
+    }
+
+    #[test]
+    fn test_parse_symlink() {
+        assert_eq!(
+            parse(&["--symlink", "-v", "releases/v2", "current"]).unwrap(),
+            App {
+                verbose: true,
+                symlink: true,
+                operations: vec![("releases/v2".into(), "current".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--symlink", "a", "b", "c"]).unwrap_err(),
+            "Expect exact 2 operands when using '--symlink'",
+        );
+        assert_eq!(
+            parse(&["--symlink", "-n", "a", "b"]).unwrap_err(),
+            "Only '--verbose' and '--sync' can be used together with '--symlink'",
+        );
+    }
+
+    #[test]
+    fn test_parse_write_atomic() {
+        assert_eq!(
+            parse(&["--write-atomic", "-f", "foo"]).unwrap(),
+            App {
+                force: true,
+                write_atomic: Some("foo".into()),
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--write-atomic", "foo", "bar"]).unwrap_err(),
+            "Expect exact 1 operand when using '--write-atomic'",
+        );
+        assert_eq!(
+            parse(&["--write-atomic", "-t", "dir", "foo"]).unwrap_err(),
+            "Cannot use '--target-directory' and '--write-atomic' together",
+        );
+        assert_eq!(
+            parse(&["--write-atomic", "--dest-dir", "dir", "foo"]).unwrap_err(),
+            "Cannot use '--dest-dir' and '--write-atomic' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_by_handle() {
+        assert_eq!(
+            parse(&["--by-handle", "/", "-t", "dir", "1:00"]).unwrap(),
+            App {
+                by_handle: Some("/".into()),
+                handle_sources: vec![HandleSource {
+                    handle: "1:00".parse().unwrap(),
+                    expected: Expected::default(),
+                    dest: Dest::IntoDir("dir".into()),
+                }],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--by-handle", "/", "-t", "dir", "1:zz"]).unwrap_err(),
+            "Invalid file handle '1:zz', expect 'TYPE:HEX'",
+        );
+        assert_eq!(
+            parse(&["--by-handle", "/", "--exchange", "1:00", "2:00"]).unwrap_err(),
+            "Cannot use '--exchange' and '--by-handle' together",
+        );
+        assert_eq!(
+            parse(&["--by-handle", "/", "--src-dir", "src", "1:00", "dest"]).unwrap_err(),
+            "Cannot use '--src-dir' and '--by-handle' together",
+        );
+        assert_eq!(
+            parse(&["--by-handle", ".", "--dest-dir", "d", "1:00", "x"]).unwrap_err(),
+            "Cannot use '--dest-dir' and '--by-handle' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_expect() {
+        let expected = Expected {
+            ino: Some(12),
+            mtime: Some("1.5".parse().unwrap()),
+            ..Expected::default()
+        };
+        assert_eq!(
+            parse(&[
+                "--expect-ino=12",
+                "--expect-mtime",
+                "1.5",
+                "-T",
+                "foo",
+                "bar"
+            ])
+            .unwrap(),
+            App {
+                expect: HashMap::from([("foo".into(), expected)]),
+                operations: vec![("foo".into(), "bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--expect-dev", "1", "foo", "bar", "/"]).unwrap_err(),
+            "Expect exact 1 source when using '--expect-dev'",
+        );
+        assert_eq!(
+            parse(&["--expect-ino", "1", "--from-file", "/dev/null"]).unwrap_err(),
+            "Cannot use '--expect-ino' and '--from-file' together, use '--batch-expect' instead",
+        );
+        assert_eq!(
+            parse(&["--batch-expect", "foo", "bar"]).unwrap_err(),
+            "'--batch-expect' can only be used together with '--from-file'",
+        );
+        assert_eq!(
+            parse(&["--expect-ino", "1", "--rename", "s/a/b/", "a"]).unwrap_err(),
+            "Cannot use '--rename' and '--expect-ino' together",
+        );
+
+        let dir = ScratchDir::new("batch-expect");
+        let file = dir.join("list");
+        fs::write(&file, "a\0b\0ino=12,mtime=1.5\0c\0d\0-\0").unwrap();
+        let file_arg = file.to_str().unwrap();
+        assert_eq!(
+            parse(&["--batch-expect", "--from-file", file_arg]).unwrap(),
+            App {
+                expect: HashMap::from([("a".into(), expected)]),
+                operations: vec![("a".into(), "b".into()), ("c".into(), "d".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--batch-expect", "-t", "dir", "--from-file", file_arg]).unwrap_err(),
+            "Invalid expectation 'b': expect 'ino=N', 'dev=N' or 'mtime=SEC[.FRACTION]'",
+        );
+        fs::write(&file, "a\0b\0").unwrap();
+        assert_eq!(
+            parse(&["--batch-expect", "--from-file", file_arg]).unwrap_err(),
+            "Missing expectation for a in '--from-file'",
+        );
+    }
+
+    #[test]
+    fn test_parse_resolve() {
+        assert_eq!(
+            parse(&["--src-dir", "in", "--dest-dir", "out", "-T", "foo", "bar"]).unwrap(),
+            App {
+                src_dir: Some("in".into()),
+                dest_dir: Some("out".into()),
+                resolve: Some(ResolveFlags::BENEATH),
+                operations: vec![("foo".into(), "bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--resolve", "no-symlinks,no-xdev", "foo", "/non/existing"]).unwrap(),
+            App {
+                resolve: Some(ResolveFlags::NO_SYMLINKS | ResolveFlags::NO_XDEV),
+                operations: vec![("foo".into(), "/non/existing".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--dest-dir", "/", "foo", "tmp"])
+                .unwrap()
+                .operations,
+            [(PathBuf::from("foo"), PathBuf::from("tmp/foo"))],
+        );
+        assert!(parse(&["--resolve", "beneath,foo", "foo", "bar"]).is_err());
+        assert_eq!(
+            parse(&[
+                "--no-follow-parent-symlinks",
+                "--src-dir",
+                "in",
+                "-T",
+                "foo",
+                "bar"
+            ])
+            .unwrap(),
+            App {
+                src_dir: Some("in".into()),
+                resolve: Some(ResolveFlags::empty()),
+                no_follow_parent_symlinks: true,
+                operations: vec![("foo".into(), "bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&[
+                "--no-follow-parent-symlinks",
+                "--resolve=none",
+                "foo",
+                "bar"
+            ])
+            .unwrap_err(),
+            "Cannot use '--resolve' and '--no-follow-parent-symlinks' together",
+        );
+        assert_eq!(
+            parse(&["--src-dir", "in", "-b", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--backup' and '--src-dir' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_journal() {
+        assert_eq!(
+            parse(&["--journal", "log", "foo", "/"]).unwrap(),
+            App {
+                journal: Some("log".into()),
+                operations: vec![("foo".into(), "/foo".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--undo", "log", "-v"]).unwrap(),
+            App {
+                verbose: true,
+                undo: Some("log".into()),
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--undo", "log", "foo"]).unwrap_err(),
+            "Expect no operand when using '--undo'",
+        );
+        for args in [
+            &["--undo", "log", "-f"][..],
+            &["--undo", "log", "-T"],
+            &["--undo", "log", "--journal", "log2"],
+        ] {
+            assert_eq!(
+                parse(args).unwrap_err(),
+                "Only '--verbose' can be used together with '--undo'",
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse(&[
+                "--rename",
+                r"s/IMG_(\d+)/photo-$1/",
+                "a/IMG_1.jpg",
+                "b",
+                "IMG_2"
+            ])
+            .unwrap(),
+            App {
+                operations: vec![
+                    ("a/IMG_1.jpg".into(), "a/photo-1.jpg".into()),
+                    ("IMG_2".into(), "photo-2".into()),
+                ],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--rename", "s/[0-9]//", "a1", "b/a2"]).unwrap(),
+            App {
+                operations: vec![("a1".into(), "a".into()), ("b/a2".into(), "b/a".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--rename", "s/[0-9]//", "a1", "a2"]).unwrap_err(),
+            "Both a1 and a2 would be renamed to a",
+        );
+        assert_eq!(
+            parse(&["--rename", "s/.*//", "a"]).unwrap_err(),
+            "Invalid name \"\" substituted from a",
+        );
+        assert_eq!(
+            parse(&["--rename", "s/a/b/"]).unwrap_err(),
+            "Missing file operand",
+        );
+        assert_eq!(
+            parse(&["--rename", "s/a/b/x", "a"]).unwrap_err(),
+            "failed to parse 's/a/b/x': Unknown flag 'x'",
+        );
+        assert_eq!(
+            parse(&["--rename", "s/a/b/", "-t", "/", "a"]).unwrap_err(),
+            "Cannot use '--target-directory' and '--rename' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_reorder() {
+        assert_eq!(
+            parse(&["--reorder", "-T", "foo", "bar"]).unwrap(),
+            App {
+                reorder: Some(CycleStrategy::Exchange),
+                operations: vec![("foo".into(), "bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--reorder", "--break-cycles", "temp", "-T", "foo", "bar"]).unwrap(),
+            App {
+                reorder: Some(CycleStrategy::TempName),
+                operations: vec![("foo".into(), "bar".into())],
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--break-cycles", "temp", "-T", "foo", "bar"]).unwrap_err(),
+            "'--break-cycles' can only be used together with '--reorder'",
+        );
+        assert_eq!(
+            parse(&["--reorder", "--break-cycles", "foo", "-T", "a", "b"]).unwrap_err(),
+            "failed to parse 'foo': Expect 'exchange' or 'temp'",
+        );
+        assert_eq!(
+            parse(&["--reorder", "--exchange", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--exchange' and '--reorder' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_edit() {
+        assert_eq!(
+            parse(&["--edit"]).unwrap(),
+            App {
+                edit: Some(Vec::new()),
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--edit", "-n", "foo", "bar"]).unwrap(),
+            App {
+                no_clobber: true,
+                edit: Some(vec!["foo".into(), "bar".into()]),
+                ..App::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--edit", "-t", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--target-directory' and '--edit' together",
+        );
+    }
+
+    #[test]
+    fn test_parse_backup() {
+        let app = App {
+            force: true,
+            operations: vec![("foo".into(), "bar".into())],
+            ..App::default()
+        };
+        assert_eq!(
+            parse(&["-fT", "--backup=numbered", "foo", "bar"]).unwrap(),
+            App {
+                backup: Some(BackupControl::Numbered),
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&["-fT", "--backup", "foo", "bar"]).unwrap(),
+            App {
+                backup: Some(BackupControl::Existing),
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&["-fbT", "--backup=simple", "foo", "bar"]).unwrap(),
+            App {
+                backup: Some(BackupControl::Simple),
+                ..app.clone()
+            }
+        );
+        assert_eq!(
+            parse(&["-fT", "--backup=off", "foo", "bar"]).unwrap(),
+            app.clone()
+        );
+        assert_eq!(
+            parse(&["-fT", "-S", ".bak", "foo", "bar"]).unwrap(),
+            App {
+                backup: Some(BackupControl::Existing),
+                suffix: Some(".bak".into()),
+                ..app
+            }
+        );
+        assert_eq!(
+            parse(&["-T", "--backup=foo", "foo", "bar"]).unwrap_err(),
+            "Invalid backup type 'foo', expect 'none', 'simple', 'numbered' or 'existing'",
+        );
+        assert_eq!(
+            parse(&["-nT", "--backup", "foo", "bar"]).unwrap_err(),
+            "Cannot use '--backup' and '--no-clobber' together",
+        );
+        assert_eq!(
+            parse(&["-T", "--backups", "foo", "bar"]).unwrap_err(),
+            "Expect exact 2 operands when using '--no-target-directory'",
+        );
+    }
+
+    #[test]
+    fn test_dry_run_missing_dir() {
+        let dir = ScratchDir::new("dry-run-missing-dir");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "").unwrap();
+        fs::write(&b, "").unwrap();
+        let nodir = dir.join("nodir");
+        let app = parse(&[
+            "-N",
+            a.to_str().unwrap(),
+            b.to_str().unwrap(),
+            nodir.to_str().unwrap(),
+        ])
+        .unwrap();
+        let status = dry_run(&app.batch(None), &app.plan().unwrap(), Summary::default());
+        assert_eq!(status, Status::TotalFailure);
+    }
+
     #[test]
     fn test_parse_dash_dash() {
         assert_eq!(
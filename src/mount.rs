@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Detect renames across mounts before they fail with a bare `EXDEV`.
+//!
+//! rename(2) requires the parent directories of both paths to be on the same
+//! mount. This also rejects bind mounts of the same filesystem, and some
+//! filesystems refuse more, eg. different btrfs subvolumes even on the same
+//! mount, which often confuses users.
+//!
+//! Devices of paths alone do not decide anything, eg. files on overlayfs
+//! report devices of their layers, so they are only used to word the
+//! explanation after the rename has actually failed.
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+
+use rustix::fs::{statx, AtFlags, Statx, StatxFlags, CWD};
+use rustix::io::Errno;
+
+use crate::{names, Error, RenameMode};
+
+/// An entry of `/proc/self/mountinfo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountInfo {
+    /// The unique mount ID.
+    pub id: u64,
+    /// The `major:minor` of the device.
+    pub device: (u32, u32),
+    /// The directory of the filesystem which forms the root of this mount.
+    /// It is not `/` for bind mounts of subdirectories.
+    pub root: PathBuf,
+    /// The mount point relative to the root of the process.
+    pub mount_point: PathBuf,
+    /// The filesystem type.
+    pub fs_type: String,
+    /// The filesystem specific source, eg. the block device.
+    pub source: String,
+}
+
+impl MountInfo {
+    /// Parse a line of `/proc/self/mountinfo`. See proc(5) for the format.
+    #[must_use]
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let id = fields.next()?.parse().ok()?;
+        let _parent_id = fields.next()?;
+        let (major, minor) = fields.next()?.split_once(':')?;
+        let device = (major.parse().ok()?, minor.parse().ok()?);
+        let root = unescape(fields.next()?);
+        let mount_point = unescape(fields.next()?);
+        // Skip mount options and optional fields.
+        fields.find(|&field| field == "-")?;
+        let fs_type = fields.next()?.to_owned();
+        let source = fields.next()?.to_owned();
+        Some(Self {
+            id,
+            device,
+            root,
+            mount_point,
+            fs_type,
+            source,
+        })
+    }
+
+    /// Find the mount of the given ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `/proc/self/mountinfo` cannot be read.
+    pub fn find(id: u64) -> io::Result<Option<Self>> {
+        let content = fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(content
+            .lines()
+            .filter_map(Self::parse)
+            .find(|info| info.id == id))
+    }
+
+    fn describe(&self) -> String {
+        let mut s = format!(
+            "{} ({} on {}",
+            self.mount_point.display(),
+            self.fs_type,
+            self.source,
+        );
+        if self.root != Path::new("/") {
+            let _ = write!(s, ", bind mount of {}", self.root.display());
+        }
+        s.push(')');
+        s
+    }
+}
+
+/// Unescape octal escapes like `\040` in mountinfo.
+fn unescape(s: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut ret = Vec::with_capacity(s.len());
+    let mut bytes = s.as_bytes();
+    while let Some((&b, rest)) = bytes.split_first() {
+        let code = rest
+            .get(..3)
+            .filter(|_| b == b'\\')
+            .and_then(|oct| u8::from_str_radix(std::str::from_utf8(oct).ok()?, 8).ok());
+        if let Some(code) = code {
+            ret.push(code);
+            bytes = &rest[3..];
+        } else {
+            ret.push(b);
+            bytes = rest;
+        }
+    }
+    std::ffi::OsString::from_vec(ret).into()
+}
+
+fn stat_parent(path: &Path) -> Option<Statx> {
+    statx(
+        CWD,
+        names::parent(path),
+        AtFlags::empty(),
+        StatxFlags::MNT_ID | StatxFlags::BASIC_STATS,
+    )
+    .ok()
+}
+
+fn stat_dir(dir: impl AsFd) -> Option<Statx> {
+    statx(
+        dir,
+        "",
+        AtFlags::EMPTY_PATH,
+        StatxFlags::MNT_ID | StatxFlags::BASIC_STATS,
+    )
+    .ok()
+}
+
+fn mount_id(st: &Statx) -> Option<u64> {
+    StatxFlags::from_bits_retain(st.stx_mask)
+        .contains(StatxFlags::MNT_ID)
+        .then_some(st.stx_mnt_id)
+}
+
+/// Predict whether renaming `src` to `dest` fails with `EXDEV`, by comparing
+/// the mounts of their parent directories with statx(2), which is what
+/// rename(2) checks. Other reasons for `EXDEV`, eg. btrfs subvolumes or
+/// overlayfs, are only known after the rename fails.
+///
+/// If any parent cannot be inspected, or the kernel does not support
+/// `STATX_MNT_ID`, the check is skipped and the rename itself will report the
+/// error.
+///
+/// # Errors
+///
+/// Returns an error of [`ErrorKind::CrossDevice`](crate::ErrorKind) with both
+/// mounts described in [`Error::hint`].
+pub fn check(src: &Path, dest: &Path, mode: RenameMode) -> Result<(), Error> {
+    let (Some(src_st), Some(dest_st)) = (stat_parent(src), stat_parent(dest)) else {
+        return Ok(());
+    };
+    match (mount_id(&src_st), mount_id(&dest_st)) {
+        (Some(src_id), Some(dest_id)) if src_id != dest_id => {
+            let mut err = Error::new(Errno::XDEV.into(), mode);
+            if let Some(detail) = describe(&src_st, &dest_st) {
+                err = err.with_detail(detail);
+            }
+            Err(err)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Explain why renaming `src` to `dest` failed with `EXDEV`, by inspecting
+/// the mounts and devices of their parent directories. Returns `None` if
+/// nothing apparent differs, eg. for directories on overlayfs.
+#[must_use]
+pub fn explain(src: &Path, dest: &Path) -> Option<String> {
+    describe(&stat_parent(src)?, &stat_parent(dest)?)
+}
+
+/// Same as [`explain`], but for parent directories already opened, eg. by a
+/// [`Resolver`](crate::resolve::Resolver).
+#[must_use]
+pub fn explain_dirs(src_dir: impl AsFd, dest_dir: impl AsFd) -> Option<String> {
+    describe(&stat_dir(src_dir)?, &stat_dir(dest_dir)?)
+}
+
+fn describe(src_st: &Statx, dest_st: &Statx) -> Option<String> {
+    let dev = |st: &Statx| (st.stx_dev_major, st.stx_dev_minor);
+    match (mount_id(src_st), mount_id(dest_st)) {
+        (Some(src_id), Some(dest_id)) if src_id != dest_id => {
+            let describe = |id| match MountInfo::find(id) {
+                Ok(Some(info)) => info.describe(),
+                _ => format!("mount #{id}"),
+            };
+            let why = if dev(src_st) == dev(dest_st) {
+                "they are different mounts of the same filesystem, eg. bind mounts, \
+                 and rename(2) never crosses mount points"
+            } else {
+                "rename(2) cannot move files between filesystems"
+            };
+            Some(format!(
+                "Source is on {} but destination is on {}: {why}",
+                describe(src_id),
+                describe(dest_id),
+            ))
+        }
+        _ if dev(src_st) != dev(dest_st) => {
+            let (src_dev, dest_dev) = (dev(src_st), dev(dest_st));
+            Some(format!(
+                "Source is on device {}:{} but destination is on device {}:{}, eg. \
+                 different btrfs subvolumes: rename(2) cannot move files between them",
+                src_dev.0, src_dev.1, dest_dev.0, dest_dev.1,
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{check, explain, MountInfo};
+    use crate::{ErrorKind, RenameMode};
+
+    #[test]
+    fn test_parse() {
+        let line = "36 35 98:0 /mnt1 /mnt/my\\040parent rw,noatime master:1 - ext3 /dev/root rw";
+        assert_eq!(
+            MountInfo::parse(line).unwrap(),
+            MountInfo {
+                id: 36,
+                device: (98, 0),
+                root: "/mnt1".into(),
+                mount_point: "/mnt/my parent".into(),
+                fs_type: "ext3".into(),
+                source: "/dev/root".into(),
+            }
+        );
+        assert_eq!(
+            MountInfo::parse(line).unwrap().describe(),
+            "/mnt/my parent (ext3 on /dev/root, bind mount of /mnt1)",
+        );
+        assert_eq!(MountInfo::parse("36 35 98:0 / /mnt rw"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        let tmp = std::env::temp_dir();
+        check(&tmp.join("foo"), &tmp.join("bar"), RenameMode::NoReplace).unwrap();
+        check(Path::new("foo"), Path::new("bar"), RenameMode::NoReplace).unwrap();
+        // Missing paths are left to the rename itself.
+        check(
+            Path::new("/non/existing/foo"),
+            Path::new("/proc/foo"),
+            RenameMode::NoReplace,
+        )
+        .unwrap();
+
+        let proc = PathBuf::from("/proc");
+        if proc.join("self").exists() {
+            let err =
+                check(&tmp.join("foo"), &proc.join("foo"), RenameMode::NoReplace).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::CrossDevice);
+            let hint = err.hint().unwrap();
+            assert!(
+                hint.contains("destination is on /proc (proc on proc)"),
+                "{hint}"
+            );
+            assert_eq!(
+                explain(&tmp.join("foo"), &proc.join("foo")).as_deref(),
+                Some(hint),
+            );
+        }
+        assert_eq!(explain(&tmp.join("foo"), &tmp.join("bar")), None);
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Split paths into parent directories and names, and pick unused names next
+//! to a path for entries which are renamed into place later.
+//!
+//! Temporary names are hidden and look like `.rawmv-KIND-PID-SEQ`, so that
+//! leftovers are recognizable and concurrent processes do not compete for the
+//! same names.
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use rustix::io::Errno;
+
+use crate::{Error, ErrorKind};
+
+/// Split `path` into the parent directory and the final component, which must
+/// be a normal name.
+pub(crate) fn split(path: &Path) -> Option<(&Path, &OsStr)> {
+    let bytes = path.as_os_str().as_bytes();
+    let trimmed = match bytes.iter().rposition(|&b| b != b'/') {
+        Some(pos) => &bytes[..=pos],
+        None => return None,
+    };
+    let (parent, name) = match trimmed.iter().rposition(|&b| b == b'/') {
+        Some(0) => (&b"/"[..], &trimmed[1..]),
+        Some(pos) => (&trimmed[..pos], &trimmed[pos + 1..]),
+        None => (&b"."[..], trimmed),
+    };
+    if name == b"." || name == b".." {
+        return None;
+    }
+    Some((
+        Path::new(OsStr::from_bytes(parent)),
+        OsStr::from_bytes(name),
+    ))
+}
+
+/// The parent directory of `path`, which is `.` for a relative file name, or
+/// if `path` does not end with a normal name.
+pub(crate) fn parent(path: &Path) -> &Path {
+    split(path).map_or(Path::new("."), |(parent, _)| parent)
+}
+
+/// The temporary name with sequence number `seq` next to `path`, for entries
+/// of `kind`, eg. `write`.
+pub(crate) fn temp_path(path: &Path, kind: &str, seq: u64) -> PathBuf {
+    path.with_file_name(format!(".rawmv-{kind}-{}-{seq}", std::process::id()))
+}
+
+/// Errors which tell that a name is taken.
+pub(crate) trait NameTaken {
+    fn name_taken(&self) -> bool;
+}
+
+impl NameTaken for Errno {
+    fn name_taken(&self) -> bool {
+        *self == Errno::EXIST
+    }
+}
+
+impl NameTaken for io::Error {
+    fn name_taken(&self) -> bool {
+        self.kind() == io::ErrorKind::AlreadyExists
+    }
+}
+
+impl NameTaken for Error {
+    fn name_taken(&self) -> bool {
+        self.kind() == ErrorKind::AlreadyExists
+    }
+}
+
+/// Call `create` with names by `name` for numbers from 0 on, until it does not
+/// fail since the name is taken. Return the last name with its result.
+pub(crate) fn first_unused<T, E: NameTaken>(
+    mut name: impl FnMut(u64) -> PathBuf,
+    mut create: impl FnMut(&Path) -> Result<T, E>,
+) -> Result<(PathBuf, T), E> {
+    for n in 0.. {
+        let path = name(n);
+        match create(&path) {
+            Ok(ret) => return Ok((path, ret)),
+            Err(err) if err.name_taken() => {}
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Create an entry of `kind` by `create` with an unused temporary name next to
+/// `path`, and return the name with the result of `create`.
+pub(crate) fn create_temp<T, E: NameTaken>(
+    path: &Path,
+    kind: &str,
+    create: impl FnMut(&Path) -> Result<T, E>,
+) -> Result<(PathBuf, T), E> {
+    first_unused(|seq| temp_path(path, kind, seq), create)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{create_temp, parent, temp_path};
+    use crate::testutil::ScratchDir;
+
+    #[test]
+    fn test_split() {
+        fn split(s: &str) -> Option<(&str, &str)> {
+            let (parent, name) = super::split(Path::new(s))?;
+            Some((parent.to_str().unwrap(), name.to_str().unwrap()))
+        }
+        assert_eq!(split("a"), Some((".", "a")));
+        assert_eq!(split("a/b//"), Some(("a", "b")));
+        assert_eq!(split("/a"), Some(("/", "a")));
+        assert_eq!(split("a//b"), Some(("a/", "b")));
+        assert_eq!(split("a/.."), None);
+        assert_eq!(split("."), None);
+        assert_eq!(split("/"), None);
+
+        assert_eq!(parent(Path::new("a/b")), Path::new("a"));
+        assert_eq!(parent(Path::new("a")), Path::new("."));
+        assert_eq!(parent(Path::new("/")), Path::new("."));
+    }
+
+    #[test]
+    fn test_create_temp() {
+        let dir = ScratchDir::new("names");
+        let path = dir.join("foo");
+        let temp = temp_path(&path, "test", 0);
+        assert_eq!(temp.parent(), Some(&*dir));
+        assert!(temp
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with(".rawmv-test-"));
+
+        // Taken names are skipped.
+        fs::write(&temp, "").unwrap();
+        let create = |temp: &Path| fs::File::create_new(temp).map(drop);
+        let (created, ()) = create_temp(&path, "test", create).unwrap();
+        assert_eq!(created, temp_path(&path, "test", 1));
+        // Other errors are returned.
+        let missing = dir.join("missing/foo");
+        assert!(create_temp(&missing, "test", create).is_err());
+    }
+}
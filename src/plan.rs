@@ -0,0 +1,256 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Order a set of renames so that no destination is clobbered by another
+//! rename in the same set, eg. for rotations like `a -> b, b -> c, c -> a`.
+//!
+//! Paths are compared literally, so `a/b` and `a/./b` are considered to be
+//! different.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::{names, RenameMode, RenameOp};
+
+/// How to break cycles of renames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CycleStrategy {
+    /// Break a cycle of `n` renames into `n - 1` exchanges. Every path exists
+    /// all the time, but the filesystem must support `RENAME_EXCHANGE`.
+    #[default]
+    Exchange,
+    /// Move one of the sources to a temporary name in the same directory
+    /// first, and move it to its destination at last.
+    TempName,
+}
+
+/// The error of [`plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// A path is moved more than once.
+    DuplicatedSource(PathBuf),
+    /// Multiple paths are moved to the same destination.
+    DuplicatedDestination(PathBuf),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicatedSource(path) => {
+                write!(f, "{} is moved more than once", path.display())
+            }
+            Self::DuplicatedDestination(path) => {
+                write!(f, "Multiple paths are moved to {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// Order renames of `(src, dest)` pairs so that each destination is moved away
+/// before it is overwritten, and break cycles by `strategy`.
+///
+/// Renames are done with `mode`, which should be [`RenameMode::NoReplace`] or
+/// [`RenameMode::Replace`], and only affects destinations outside of the set.
+/// Renames whose source and destination are the same are dropped.
+///
+/// # Errors
+///
+/// Returns an error if a path is moved more than once, or multiple paths are
+/// moved to the same destination.
+pub fn plan(
+    pairs: &[(PathBuf, PathBuf)],
+    mode: RenameMode,
+    strategy: CycleStrategy,
+) -> Result<Vec<RenameOp>, PlanError> {
+    let pairs = pairs
+        .iter()
+        .filter(|(src, dest)| src != dest)
+        .collect::<Vec<_>>();
+
+    let mut src_idx = HashMap::with_capacity(pairs.len());
+    let mut dest_idx = HashMap::with_capacity(pairs.len());
+    for (i, (src, dest)) in pairs.iter().enumerate() {
+        if src_idx.insert(src, i).is_some() {
+            return Err(PlanError::DuplicatedSource(src.clone()));
+        }
+        if dest_idx.insert(dest, i).is_some() {
+            return Err(PlanError::DuplicatedDestination(dest.clone()));
+        }
+    }
+
+    // Rename `i` must wait for `next[i]`, which moves its destination away.
+    // Since both sources and destinations are unique, the graph consists of
+    // disjoint chains and cycles only.
+    let next = pairs
+        .iter()
+        .map(|(_, dest)| src_idx.get(dest).copied())
+        .collect::<Vec<_>>();
+
+    let mut state = vec![State::Pending; pairs.len()];
+    let mut ops = Vec::with_capacity(pairs.len());
+    let mut temp_count = 0;
+    for start in 0..pairs.len() {
+        let mut path = Vec::new();
+        let mut cur = Some(start);
+        let mut is_cycle = false;
+        while let Some(i) = cur {
+            match state[i] {
+                State::Done => break,
+                State::Walking => {
+                    // Nothing leads into a cycle from outside, so we must be
+                    // back to the start.
+                    debug_assert_eq!(i, start);
+                    is_cycle = true;
+                    break;
+                }
+                State::Pending => {}
+            }
+            state[i] = State::Walking;
+            path.push(i);
+            cur = next[i];
+        }
+
+        if is_cycle {
+            let first = &pairs[path[0]].0;
+            match strategy {
+                CycleStrategy::Exchange => {
+                    for &i in &path[..path.len() - 1] {
+                        ops.push(RenameOp::new(first, &pairs[i].1, RenameMode::Exchange));
+                    }
+                }
+                CycleStrategy::TempName => {
+                    let temp = names::temp_path(first, "cycle", temp_count);
+                    temp_count += 1;
+                    ops.push(RenameOp::new(first, &temp, RenameMode::NoReplace));
+                    for &i in path[1..].iter().rev() {
+                        let (src, dest) = pairs[i];
+                        ops.push(RenameOp::new(src, dest, RenameMode::NoReplace));
+                    }
+                    ops.push(RenameOp::new(
+                        temp,
+                        &pairs[path[0]].1,
+                        RenameMode::NoReplace,
+                    ));
+                }
+            }
+        } else {
+            for &i in path.iter().rev() {
+                let (src, dest) = pairs[i];
+                ops.push(RenameOp::new(src, dest, mode));
+            }
+        }
+
+        for i in path {
+            state[i] = State::Done;
+        }
+    }
+
+    Ok(ops)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Pending,
+    Walking,
+    Done,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{plan, CycleStrategy, PlanError};
+    use crate::names::temp_path;
+    use crate::{RenameMode, RenameOp};
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|&(src, dest)| (src.into(), dest.into()))
+            .collect()
+    }
+
+    fn ops(ops: &[(&str, &str, RenameMode)]) -> Vec<RenameOp> {
+        ops.iter()
+            .map(|&(src, dest, mode)| RenameOp::new(src, dest, mode))
+            .collect()
+    }
+
+    #[test]
+    fn test_chain() {
+        use RenameMode::NoReplace as N;
+
+        let input = pairs(&[("a", "b"), ("b", "c"), ("x", "y"), ("c", "d"), ("e", "e")]);
+        assert_eq!(
+            plan(&input, N, CycleStrategy::Exchange).unwrap(),
+            ops(&[("c", "d", N), ("b", "c", N), ("a", "b", N), ("x", "y", N)]),
+        );
+        assert_eq!(
+            plan(&input[1..], RenameMode::Replace, CycleStrategy::Exchange).unwrap(),
+            ops(&[
+                ("c", "d", RenameMode::Replace),
+                ("b", "c", RenameMode::Replace),
+                ("x", "y", RenameMode::Replace),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_cycle_exchange() {
+        use RenameMode::{Exchange as X, NoReplace as N};
+
+        let input = pairs(&[("a", "b"), ("b", "a")]);
+        assert_eq!(
+            plan(&input, N, CycleStrategy::Exchange).unwrap(),
+            ops(&[("a", "b", X)]),
+        );
+
+        let input = pairs(&[("a", "b"), ("q", "p"), ("b", "c"), ("p", "q"), ("c", "a")]);
+        assert_eq!(
+            plan(&input, N, CycleStrategy::Exchange).unwrap(),
+            ops(&[("a", "b", X), ("a", "c", X), ("q", "p", X)]),
+        );
+    }
+
+    #[test]
+    fn test_cycle_temp() {
+        use RenameMode::NoReplace as N;
+
+        let input = pairs(&[("d/a", "d/b"), ("d/b", "d/c"), ("d/c", "d/a")]);
+        let temp = temp_path("d/a".as_ref(), "cycle", 0);
+        assert_eq!(temp.parent().unwrap(), PathBuf::from("d"));
+        let temp = temp.to_str().unwrap();
+        assert_eq!(
+            plan(&input, N, CycleStrategy::TempName).unwrap(),
+            ops(&[
+                ("d/a", temp, N),
+                ("d/c", "d/a", N),
+                ("d/b", "d/c", N),
+                (temp, "d/b", N),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let mode = RenameMode::NoReplace;
+        assert_eq!(
+            plan(
+                &pairs(&[("a", "b"), ("a", "c")]),
+                mode,
+                CycleStrategy::Exchange
+            )
+            .unwrap_err(),
+            PlanError::DuplicatedSource("a".into()),
+        );
+        assert_eq!(
+            plan(
+                &pairs(&[("a", "c"), ("b", "c")]),
+                mode,
+                CycleStrategy::Exchange
+            )
+            .unwrap_err(),
+            PlanError::DuplicatedDestination("c".into()),
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Replace a directory with a new one atomically, eg. to deploy a build.
+//!
+//! A non-empty directory cannot be replaced by rename(2), which fails with
+//! `ENOTEMPTY`. Instead, the new directory is exchanged with the old one by
+//! `RENAME_EXCHANGE`, so readers of the old path always see either the
+//! complete old tree or the complete new one. The displaced old tree is left
+//! at the path of the new one, and then removed or renamed aside.
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rustix::io::Errno;
+
+use crate::{Error, RenameMode, RenameOp};
+
+/// Exchange the directory `new` with the directory `old`, leaving the old tree
+/// at `new`. If `old` does not exist, `new` is renamed to it without replacing
+/// anything, and `false` is returned.
+///
+/// # Errors
+///
+/// Returns an error if either path is not a directory, or the rename fails.
+/// Nothing is changed in this case.
+pub fn exchange_dirs(new: &Path, old: &Path) -> Result<bool, Error> {
+    let mode = RenameMode::Exchange;
+    let is_dir = |path: &Path| match path.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => Ok(true),
+        Ok(_) => Err(Error::new(Errno::NOTDIR.into(), mode)
+            .with_detail(format!("{} is not a directory", path.display()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(Error::new(err, mode)),
+    };
+    if !is_dir(new)? {
+        return Err(Error::new(Errno::NOENT.into(), mode)
+            .with_detail(format!("{} does not exist", new.display())));
+    }
+    if !is_dir(old)? {
+        RenameOp::new(new, old, RenameMode::NoReplace).execute()?;
+        return Ok(false);
+    }
+    RenameOp::new(new, old, mode).execute()?;
+    Ok(true)
+}
+
+/// Dispose of the old tree at `displaced` after [`exchange_dirs`]. It is
+/// removed recursively, or if `keep_suffix` is given, renamed to `old` with
+/// the suffix appended without replacing anything, and the new path is
+/// returned.
+///
+/// # Errors
+///
+/// Returns an error if the tree cannot be removed or renamed. It may be
+/// partially removed in this case.
+pub fn dispose(
+    displaced: &Path,
+    old: &Path,
+    keep_suffix: Option<&OsStr>,
+) -> Result<Option<PathBuf>, Error> {
+    if let Some(suffix) = keep_suffix {
+        let mut kept = OsString::from(old);
+        kept.push(suffix);
+        let kept = PathBuf::from(kept);
+        RenameOp::new(displaced, &kept, RenameMode::NoReplace).execute()?;
+        return Ok(Some(kept));
+    }
+    std::fs::remove_dir_all(displaced).map_err(|err| {
+        Error::new(err, RenameMode::NoReplace)
+            .with_detail(format!("Cannot remove {}", displaced.display()))
+    })?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use super::{dispose, exchange_dirs};
+    use crate::testutil::ScratchDir;
+
+    #[test]
+    fn test_replace_dir() {
+        let dir = ScratchDir::new("replace");
+        fs::create_dir_all(dir.join("old/sub")).unwrap();
+        fs::write(dir.join("old/sub/foo"), "old").unwrap();
+        fs::create_dir(dir.join("new")).unwrap();
+        fs::write(dir.join("new/foo"), "new").unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        let (new, old) = (dir.join("new"), dir.join("old"));
+
+        assert!(exchange_dirs(&new, &dir.join("file")).is_err());
+        assert!(exchange_dirs(&dir.join("missing"), &old).is_err());
+
+        assert!(exchange_dirs(&new, &old).unwrap());
+        assert_eq!(fs::read_to_string(old.join("foo")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(new.join("sub/foo")).unwrap(), "old");
+        assert_eq!(dispose(&new, &old, None).unwrap(), None);
+        assert!(!new.exists());
+
+        fs::create_dir(&new).unwrap();
+        assert!(exchange_dirs(&new, &old).unwrap());
+        let kept = dispose(&new, &old, Some(OsStr::new(".bak"))).unwrap();
+        assert_eq!(kept, Some(dir.join("old.bak")));
+        assert_eq!(fs::read_to_string(dir.join("old.bak/foo")).unwrap(), "new");
+
+        assert!(!exchange_dirs(&dir.join("old.bak"), &new).unwrap());
+        assert!(new.join("foo").exists());
+    }
+}
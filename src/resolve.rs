@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Resolve parent directories of paths with restrictions, so that a rename
+//! cannot be redirected by symlinks swapped into parent components.
+//!
+//! The parent directory is opened once by openat2(2) with `RESOLVE_*` flags,
+//! and the rename is then performed relative to the opened directory, so only
+//! the final component is looked up by renameat2(2) itself, which never
+//! follows it.
+//!
+//! Alternatively, parent directories can be walked component by component
+//! with `O_PATH | O_NOFOLLOW`, refusing any symlink on the way. This does not
+//! need openat2(2), which is only available since Linux 5.6.
+use std::ffi::OsStr;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::path::{Component, Path};
+
+use rustix::fs::{self, FileType, Mode, OFlags, ResolveFlags};
+use rustix::io::Errno;
+
+use crate::names::split;
+
+/// Resolve paths relative to a base directory with `RESOLVE_*` flags.
+#[derive(Debug)]
+pub struct Resolver {
+    base: Option<OwnedFd>,
+    flags: ResolveFlags,
+    walk: bool,
+}
+
+impl Resolver {
+    /// Resolve paths relative to the current directory.
+    #[must_use]
+    pub fn new(flags: ResolveFlags) -> Self {
+        Self {
+            base: None,
+            flags,
+            walk: false,
+        }
+    }
+
+    /// Resolve paths relative to `dir`, which is opened now, so it is not
+    /// affected by later changes of the path. `dir` itself is resolved without
+    /// restrictions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` cannot be opened as a directory.
+    pub fn open(dir: &Path, flags: ResolveFlags) -> io::Result<Self> {
+        let fd = fs::openat(
+            fs::CWD,
+            dir,
+            OFlags::PATH | OFlags::DIRECTORY | OFlags::CLOEXEC,
+            Mode::empty(),
+        )?;
+        Ok(Self {
+            base: Some(fd),
+            flags,
+            walk: false,
+        })
+    }
+
+    /// Walk parent directories component by component instead of using
+    /// openat2(2), and fail with `ELOOP` on any symlink. `..` is rejected with
+    /// `EXDEV` if there is a base directory. The `RESOLVE_*` flags are
+    /// ignored.
+    #[must_use]
+    pub fn walking(self) -> Self {
+        Self { walk: true, ..self }
+    }
+
+    fn base(&self) -> BorrowedFd<'_> {
+        match &self.base {
+            Some(fd) => fd.as_fd(),
+            None => fs::CWD,
+        }
+    }
+
+    /// Open the parent directory of `path` as an `O_PATH` file descriptor, and
+    /// return it together with the final component.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EINVAL` if the final component is `.` or `..`, or the error of
+    /// openat2(2), eg. `EXDEV` if the parent escapes the base directory with
+    /// `RESOLVE_BENEATH`, or `ELOOP` if it contains symlinks with
+    /// `RESOLVE_NO_SYMLINKS`.
+    pub fn open_parent<'a>(&self, path: &'a Path) -> io::Result<(OwnedFd, &'a OsStr)> {
+        let (parent, name) = split(path).ok_or(Errno::INVAL)?;
+        if self.walk {
+            return Ok((self.walk(parent)?, name));
+        }
+        let fd = fs::openat2(
+            self.base(),
+            parent,
+            OFlags::PATH | OFlags::DIRECTORY | OFlags::CLOEXEC,
+            Mode::empty(),
+            self.flags,
+        )?;
+        Ok((fd, name))
+    }
+
+    fn walk(&self, dir: &Path) -> io::Result<OwnedFd> {
+        let open = |at: BorrowedFd<'_>, name: &Path| {
+            fs::openat(
+                at,
+                name,
+                OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+                Mode::empty(),
+            )
+        };
+        let mut cur = open(self.base(), Path::new("."))?;
+        for component in dir.components() {
+            let fd = match component {
+                Component::RootDir | Component::ParentDir if self.base.is_some() => {
+                    return Err(Errno::XDEV.into());
+                }
+                Component::RootDir => open(fs::CWD, Path::new("/"))?,
+                Component::CurDir => continue,
+                Component::ParentDir => open(cur.as_fd(), Path::new(".."))?,
+                Component::Normal(name) => open(cur.as_fd(), Path::new(name))?,
+                Component::Prefix(_) => unreachable!(),
+            };
+            match FileType::from_raw_mode(fs::fstat(&fd)?.st_mode) {
+                FileType::Directory => cur = fd,
+                FileType::Symlink => return Err(Errno::LOOP.into()),
+                _ => return Err(Errno::NOTDIR.into()),
+            }
+        }
+        Ok(cur)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use rustix::fs::ResolveFlags;
+
+    use super::Resolver;
+    use crate::testutil::ScratchDir;
+    use crate::{ErrorKind, RenameMode, RenameOp};
+
+    #[test]
+    fn test_resolve() {
+        let dir = ScratchDir::new("resolve");
+        fs::create_dir_all(dir.join("base/sub")).unwrap();
+        symlink("sub", dir.join("base/link")).unwrap();
+        fs::write(dir.join("base/sub/foo"), "").unwrap();
+        fs::write(dir.join("outside"), "").unwrap();
+
+        let beneath = Resolver::open(&dir.join("base"), ResolveFlags::BENEATH).unwrap();
+        let no_symlinks = Resolver::open(&dir.join("base"), ResolveFlags::NO_SYMLINKS).unwrap();
+        let op = |src: &str, dest: &str| RenameOp::new(src, dest, RenameMode::NoReplace);
+
+        op("sub/foo", "link/bar")
+            .execute_with(&beneath, &beneath)
+            .unwrap();
+        assert!(dir.join("base/sub/bar").exists());
+
+        let err = op("link/bar", "foo")
+            .execute_with(&no_symlinks, &no_symlinks)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Restricted);
+        let err = op("../outside", "foo")
+            .execute_with(&beneath, &beneath)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Restricted);
+        assert!(dir.join("outside").exists());
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = ScratchDir::new("walk");
+        fs::create_dir_all(dir.join("base/sub")).unwrap();
+        symlink("sub", dir.join("base/link")).unwrap();
+        fs::write(dir.join("base/sub/foo"), "").unwrap();
+
+        let walking = Resolver::open(&dir.join("base"), ResolveFlags::empty())
+            .unwrap()
+            .walking();
+        let op = |src: &str, dest: &str| RenameOp::new(src, dest, RenameMode::NoReplace);
+
+        let err = op("link/foo", "foo")
+            .execute_with(&walking, &walking)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Restricted);
+        let err = op("sub/../sub/foo", "foo")
+            .execute_with(&walking, &walking)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Restricted);
+
+        op("./sub//foo", "bar")
+            .execute_with(&walking, &walking)
+            .unwrap();
+        assert!(dir.join("base/bar").exists());
+
+        // Absolute paths are allowed without a base directory.
+        let walking = Resolver::new(ResolveFlags::empty()).walking();
+        op(
+            dir.join("base/bar").to_str().unwrap(),
+            dir.join("base/sub/bar").to_str().unwrap(),
+        )
+        .execute_with(&walking, &walking)
+        .unwrap();
+        assert!(dir.join("base/sub/bar").exists());
+    }
+}
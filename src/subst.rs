@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! sed(1)-like substitution on file names.
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::str::FromStr;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+/// A substitution expression in the form of `s/PATTERN/REPLACEMENT/FLAGS`.
+///
+/// Any character can be used as the delimiter instead of `/`, and it can be
+/// escaped by a backslash in the pattern and the replacement. The replacement
+/// refers to capture groups by `$1` or `${name}`. Supported flags are `g` to
+/// replace all matches instead of only the first one, and `i` to match case
+/// insensitively.
+///
+/// Names are matched byte-wise, so that non-UTF-8 names are never mangled.
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: Vec<u8>,
+    global: bool,
+}
+
+impl Substitution {
+    /// Apply the substitution on a name. Returns `None` if the pattern doesn't
+    /// match.
+    #[must_use]
+    pub fn apply(&self, name: &OsStr) -> Option<OsString> {
+        let name = name.as_bytes();
+        if !self.regex.is_match(name) {
+            return None;
+        }
+        // Zero means no limit.
+        let limit = usize::from(!self.global);
+        let ret = match self.regex.replacen(name, limit, &self.replacement[..]) {
+            Cow::Borrowed(s) => s.to_vec(),
+            Cow::Owned(s) => s,
+        };
+        Some(OsString::from_vec(ret))
+    }
+}
+
+/// The error of parsing a [`Substitution`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const FORM_ERROR: &str = "Expression should be in form of 's/PATTERN/REPLACEMENT/'";
+
+impl FromStr for Substitution {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |msg: &str| ParseError(msg.to_owned());
+
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            return Err(err(FORM_ERROR));
+        }
+        let delim = chars
+            .next()
+            .filter(|&c| c != '\\' && c != '\n')
+            .ok_or_else(|| err("Missing or invalid delimiter after 's'"))?;
+
+        // Split by unescaped delimiters. Only the escaped delimiter is
+        // unescaped, other escapes are kept for the regex.
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let part = parts.last_mut().unwrap();
+            if escaped {
+                if c != delim {
+                    part.push('\\');
+                }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delim {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+        if escaped {
+            parts.last_mut().unwrap().push('\\');
+        }
+        let [pattern, replacement, flags]: [String; 3] =
+            parts.try_into().map_err(|_| err(FORM_ERROR))?;
+
+        let mut global = false;
+        let mut builder = RegexBuilder::new(&pattern);
+        builder.unicode(false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => return Err(ParseError(format!("Unknown flag '{flag}'"))),
+            }
+        }
+        let regex = builder
+            .build()
+            .map_err(|e| ParseError(format!("Invalid pattern: {e}")))?;
+
+        Ok(Self {
+            regex,
+            replacement: replacement.into_bytes(),
+            global,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    use super::Substitution;
+
+    fn apply(expr: &str, name: &[u8]) -> Option<Vec<u8>> {
+        let subst = expr.parse::<Substitution>().unwrap();
+        subst.apply(OsStr::from_bytes(name)).map(OsString::into_vec)
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            apply(r"s/IMG_(\d+)\.jpg/photo-$1.jpg/", b"IMG_0042.jpg").unwrap(),
+            b"photo-0042.jpg",
+        );
+        assert_eq!(apply(r"s/IMG_(\d+)/photo/", b"DSC_0042.jpg"), None);
+        assert_eq!(apply("s/a/b/", b"aaa").unwrap(), b"baa");
+        assert_eq!(apply("s/a/b/g", b"aaa").unwrap(), b"bbb");
+        assert_eq!(apply("s/A/b/gi", b"aAa").unwrap(), b"bbb");
+        assert_eq!(apply("s|/|_|", b"a").as_deref(), None);
+        assert_eq!(apply(r"s/\//_/", b"a").as_deref(), None);
+        assert_eq!(apply(r"s#\##-#", b"a#b").unwrap(), b"a-b");
+        assert_eq!(apply(r"s/(?P<n>\d)/<${n}>/", b"x1").unwrap(), b"x<1>");
+
+        // Non-UTF-8 bytes are preserved.
+        assert_eq!(apply("s/^a/b/", b"a\xFF.txt").unwrap(), b"b\xFF.txt");
+        assert_eq!(apply(r"s/\xFF/_/", b"a\xFF").unwrap(), b"a_");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let parse = |s: &str| s.parse::<Substitution>().unwrap_err().to_string();
+        assert_eq!(
+            parse("y/a/b/"),
+            "Expression should be in form of 's/PATTERN/REPLACEMENT/'",
+        );
+        assert_eq!(
+            parse("s/a/b"),
+            "Expression should be in form of 's/PATTERN/REPLACEMENT/'",
+        );
+        assert_eq!(
+            parse("s/a/b/c/"),
+            "Expression should be in form of 's/PATTERN/REPLACEMENT/'",
+        );
+        assert_eq!(parse("s"), "Missing or invalid delimiter after 's'");
+        assert_eq!(parse("s/a/b/x"), "Unknown flag 'x'");
+        assert!(parse("s/(/b/").starts_with("Invalid pattern: "));
+    }
+}
@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Point a symlink to a new target atomically, like `ln -sfn` but without a
+//! moment where the link is missing.
+//!
+//! A new symlink is created under a temporary name in the same directory,
+//! and then renamed over the link, which atomically replaces it.
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use crate::{names, Error, RenameMode, RenameOp};
+
+/// Create or replace the symlink `link` pointing to `target`. `target` is
+/// stored as is, so a relative one is relative to the directory of `link`.
+///
+/// The temporary symlink is removed if the final rename fails.
+///
+/// # Errors
+///
+/// Returns an error if the temporary symlink cannot be created, or the rename
+/// fails, eg. if `link` is a directory.
+pub fn retarget(target: &Path, link: &Path) -> Result<(), Error> {
+    let op = RenameOp::new(create_temp(target, link)?, link, RenameMode::Replace);
+    op.execute().inspect_err(|_| {
+        let _ = std::fs::remove_file(&op.src);
+    })
+}
+
+/// Create a symlink to `target` with an unused temporary name next to `link`,
+/// and return its path.
+fn create_temp(target: &Path, link: &Path) -> Result<PathBuf, Error> {
+    let (temp, ()) = names::create_temp(link, "symlink", |temp| {
+        symlink(target, temp).map_err(|err| {
+            Error::new(err, RenameMode::Replace).with_detail(format!(
+                "Cannot create temporary symlink {}",
+                temp.display()
+            ))
+        })
+    })?;
+    Ok(temp)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::retarget;
+    use crate::names::temp_path;
+    use crate::testutil::ScratchDir;
+    use crate::ErrorKind;
+
+    #[test]
+    fn test_retarget() {
+        let dir = ScratchDir::new("symlink");
+        fs::create_dir_all(dir.join("subdir")).unwrap();
+        let link = dir.join("current");
+
+        retarget(Path::new("v1"), &link).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("v1"));
+        // Temporary names already taken are skipped.
+        fs::write(temp_path(&link, "symlink", 0), "").unwrap();
+        retarget(Path::new("v2"), &link).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("v2"));
+
+        let err = retarget(Path::new("v3"), &dir.join("subdir")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(!temp_path(&link, "symlink", 1).exists());
+        let entries = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(entries, 3);
+    }
+}
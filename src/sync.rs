@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Make renames durable by syncing their parent directories.
+//!
+//! A rename only changes directory entries, which may still be lost on a
+//! power failure until the directories themselves are fsync(2)ed.
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use rustix::fs::{self, Mode, OFlags};
+use rustix::io::Errno;
+
+use crate::names::split;
+use crate::resolve::Resolver;
+
+/// Sync parent directories of renamed paths, each directory only once.
+///
+/// Directories are identified by their device and inode numbers, so that
+/// different paths to the same directory are synced only once.
+#[derive(Debug, Default)]
+pub struct DirSync {
+    synced: HashSet<(u64, u64)>,
+}
+
+impl DirSync {
+    /// Create an empty set of synced directories.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// fsync(2) the parent directory of `path`, unless it is synced already.
+    /// The parent is resolved by `resolver`, or relative to the current
+    /// directory if it is `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be opened or synced.
+    pub fn sync_parent(&mut self, path: &Path, resolver: Option<&Resolver>) -> io::Result<()> {
+        let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC;
+        let fd = if let Some(resolver) = resolver {
+            let (parent, _) = resolver.open_parent(path)?;
+            fs::openat(&parent, ".", flags, Mode::empty())?
+        } else {
+            let (parent, _) = split(path).ok_or(Errno::INVAL)?;
+            fs::openat(fs::CWD, parent, flags, Mode::empty())?
+        };
+        let st = fs::fstat(&fd)?;
+        if self.synced.insert((st.st_dev, st.st_ino)) {
+            fs::fsync(&fd)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::DirSync;
+    use crate::testutil::ScratchDir;
+
+    #[test]
+    fn test_sync_parent() {
+        let dir = ScratchDir::new("sync");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        let mut sync = DirSync::new();
+        sync.sync_parent(&dir.join("foo"), None).unwrap();
+        sync.sync_parent(&dir.join("sub/../bar"), None).unwrap();
+        sync.sync_parent(&dir.join("sub/foo"), None).unwrap();
+        assert_eq!(sync.synced.len(), 2);
+        sync.sync_parent(Path::new("foo"), None).unwrap();
+        assert!(sync.sync_parent(&dir.join("missing/foo"), None).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Helpers shared by tests of the library and the binary.
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty scratch directory, removed with its contents when dropped, even if
+/// the test panics.
+#[derive(Debug)]
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// Create a scratch directory in the temporary directory. `name` must be
+    /// unique among tests, since they run in parallel.
+    pub fn new(name: &str) -> Self {
+        Self::new_in(&std::env::temp_dir(), name)
+    }
+
+    /// Create a scratch directory in `parent`, eg. on a specific filesystem.
+    pub fn new_in(parent: &Path, name: &str) -> Self {
+        let dir = parent.join(format!("rawmv-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
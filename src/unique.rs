@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Rename to a unique name if the destination exists, eg. `name (1).ext`.
+//!
+//! Names are tried one by one with `RENAME_NOREPLACE`, so a name taken
+//! concurrently by another process is skipped as well, and nothing is ever
+//! overwritten.
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::str::FromStr;
+
+use rustix::io::Errno;
+
+use crate::{names, Error, RenameOp};
+
+/// The format of unique names. `{name}` is replaced by the file name without
+/// the extension, `{ext}` by the extension including the dot, if any, and
+/// `{n}` by the number, starting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameFormat(Vec<Piece>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Name,
+    Ext,
+    Number,
+}
+
+impl Default for NameFormat {
+    fn default() -> Self {
+        "{name} ({n}){ext}".parse().unwrap()
+    }
+}
+
+/// The error of parsing a [`NameFormat`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError(String, &'static str);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid name format '{}': {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for NameFormat {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |reason| ParseError(s.to_owned(), reason);
+        if s.contains('/') {
+            return Err(err("it must not contain '/'"));
+        }
+        let mut pieces = Vec::new();
+        let mut rest = s;
+        while let Some(pos) = rest.find('{') {
+            let end = rest[pos..].find('}').ok_or_else(|| err("unclosed '{'"))?;
+            if pos != 0 {
+                pieces.push(Piece::Literal(rest[..pos].to_owned()));
+            }
+            pieces.push(match &rest[pos + 1..pos + end] {
+                "name" => Piece::Name,
+                "ext" => Piece::Ext,
+                "n" => Piece::Number,
+                _ => return Err(err("expect '{name}', '{ext}' or '{n}'")),
+            });
+            rest = &rest[pos + end + 1..];
+        }
+        if !rest.is_empty() {
+            pieces.push(Piece::Literal(rest.to_owned()));
+        }
+        if !pieces.contains(&Piece::Number) {
+            return Err(err("it must contain '{n}'"));
+        }
+        Ok(Self(pieces))
+    }
+}
+
+impl NameFormat {
+    /// Format the `n`-th unique name for `file_name`.
+    #[must_use]
+    pub fn format(&self, file_name: &OsStr, n: u64) -> OsString {
+        let file_name = file_name.as_bytes();
+        // Like `Path::file_stem`, a leading dot does not start an extension.
+        let (name, ext) = match file_name.iter().rposition(|&b| b == b'.') {
+            Some(pos) if pos != 0 => file_name.split_at(pos),
+            _ => (file_name, &b""[..]),
+        };
+        let mut out = Vec::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Literal(s) => out.extend_from_slice(s.as_bytes()),
+                Piece::Name => out.extend_from_slice(name),
+                Piece::Ext => out.extend_from_slice(ext),
+                Piece::Number => out.extend_from_slice(n.to_string().as_bytes()),
+            }
+        }
+        OsString::from_vec(out)
+    }
+}
+
+/// Retry `op`, which failed since its destination exists, with unique names
+/// of the destination by `format`, until one of them succeeds. `execute`
+/// performs each attempt. Return the operation which succeeded.
+///
+/// # Errors
+///
+/// Returns the first error other than [`ErrorKind::AlreadyExists`], or
+/// `EINVAL` if the destination does not end with a normal name.
+pub fn rename_unique(
+    op: &RenameOp,
+    format: &NameFormat,
+    mut execute: impl FnMut(&RenameOp) -> Result<(), Error>,
+) -> Result<RenameOp, Error> {
+    let file_name = op
+        .dest
+        .file_name()
+        .ok_or_else(|| Error::new(Errno::INVAL.into(), op.mode))?;
+    let (dest, ()) = names::first_unused(
+        |n| op.dest.with_file_name(format.format(file_name, n + 1)),
+        |dest| execute(&RenameOp::new(&op.src, dest, op.mode)),
+    )?;
+    Ok(RenameOp::new(&op.src, dest, op.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs;
+
+    use super::{rename_unique, NameFormat};
+    use crate::testutil::ScratchDir;
+    use crate::{RenameMode, RenameOp};
+
+    #[test]
+    fn test_format() {
+        let format = NameFormat::default();
+        let name = |path: &str| format.format(OsStr::new(path), 2).into_string().unwrap();
+        assert_eq!(name("a.tar.gz"), "a.tar (2).gz");
+        assert_eq!(name("a"), "a (2)");
+        assert_eq!(name(".bashrc"), ".bashrc (2)");
+
+        let format = "{n}-{name}{ext}.dup".parse::<NameFormat>().unwrap();
+        assert_eq!(format.format(OsStr::new("a.txt"), 10), "10-a.txt.dup");
+
+        assert_eq!(
+            "{name}".parse::<NameFormat>().unwrap_err().to_string(),
+            "Invalid name format '{name}': it must contain '{n}'",
+        );
+        assert_eq!(
+            "{n}{foo}".parse::<NameFormat>().unwrap_err().to_string(),
+            "Invalid name format '{n}{foo}': expect '{name}', '{ext}' or '{n}'",
+        );
+        assert!("{n".parse::<NameFormat>().is_err());
+        assert!("{n}/a".parse::<NameFormat>().is_err());
+    }
+
+    #[test]
+    fn test_rename_unique() {
+        let dir = ScratchDir::new("unique");
+        for name in ["src", "a.txt", "a (1).txt", "a (3).txt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let op = RenameOp::new(dir.join("src"), dir.join("a.txt"), RenameMode::NoReplace);
+        let done = rename_unique(&op, &NameFormat::default(), RenameOp::execute).unwrap();
+        assert_eq!(done.dest, dir.join("a (2).txt"));
+        assert_eq!(fs::read_to_string(&done.dest).unwrap(), "src");
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "a.txt");
+
+        assert!(rename_unique(&op, &NameFormat::default(), RenameOp::execute).is_err());
+        let op = RenameOp::new(dir.join("a.txt"), dir.join(".."), RenameMode::NoReplace);
+        assert!(rename_unique(&op, &NameFormat::default(), RenameOp::execute).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Decide whether to replace existing destinations by modification times,
+//! like `--update` of mv(1).
+//!
+//! Timestamps are compared by statx(2) with nanoseconds. Note that both paths
+//! are inspected before the rename, and there is no way to make the rename
+//! conditional on them. A destination modified in between may still be
+//! replaced, so this is only suitable for files not being written
+//! concurrently.
+use std::path::Path;
+
+use rustix::fs::{statx, AtFlags, StatxFlags, StatxTimestamp, CWD};
+use rustix::io::Errno;
+
+use crate::{Error, RenameMode};
+
+/// Which existing destinations to replace, as the `UPDATE` of `--update` of
+/// mv(1).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Update {
+    /// Replace all of them.
+    All,
+    /// Replace none of them.
+    None,
+    /// Replace those with modification times older than sources.
+    #[default]
+    Older,
+}
+
+impl Update {
+    /// Check whether the existing `dest` should be replaced by `src`. A
+    /// destination which disappears in the meantime is always replaced.
+    ///
+    /// Symlinks are not followed, since renames never follow them either.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source, or the destination other than being
+    /// missing, cannot be inspected.
+    pub fn replaces(self, src: &Path, dest: &Path, mode: RenameMode) -> Result<bool, Error> {
+        match self {
+            Self::All => Ok(true),
+            Self::None => Ok(false),
+            Self::Older => {
+                let mtime = |path: &Path| -> Result<_, Errno> {
+                    let st = statx(CWD, path, AtFlags::SYMLINK_NOFOLLOW, StatxFlags::MTIME)?;
+                    let StatxTimestamp {
+                        tv_sec, tv_nsec, ..
+                    } = st.stx_mtime;
+                    Ok((tv_sec, tv_nsec))
+                };
+                let fail = |err: Errno, path: &Path| {
+                    let detail = format!("Cannot compare modification time of {}", path.display());
+                    Error::new(err.into(), mode).with_detail(detail)
+                };
+                let src_mtime = mtime(src).map_err(|err| fail(err, src))?;
+                match mtime(dest) {
+                    Ok(dest_mtime) => Ok(dest_mtime < src_mtime),
+                    Err(Errno::NOENT) => Ok(true),
+                    Err(err) => Err(fail(err, dest)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, FileTimes};
+    use std::time::{Duration, SystemTime};
+
+    use super::Update;
+    use crate::testutil::ScratchDir;
+    use crate::RenameMode;
+
+    #[test]
+    fn test_replaces() {
+        let dir = ScratchDir::new("update");
+        let (old, new, missing) = (dir.join("old"), dir.join("new"), dir.join("missing"));
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for (path, nanos) in [(&old, 1), (&new, 2)] {
+            File::create(path)
+                .unwrap()
+                .set_times(FileTimes::new().set_modified(t + Duration::from_nanos(nanos)))
+                .unwrap();
+        }
+
+        let replaces =
+            |update: Update, src, dest| update.replaces(src, dest, RenameMode::NoReplace);
+        assert!(replaces(Update::Older, &new, &old).unwrap());
+        assert!(!replaces(Update::Older, &old, &new).unwrap());
+        assert!(!replaces(Update::Older, &old, &old).unwrap());
+        assert!(replaces(Update::Older, &old, &missing).unwrap());
+        assert!(replaces(Update::Older, &missing, &old).is_err());
+        assert!(replaces(Update::All, &old, &new).unwrap());
+        assert!(!replaces(Update::None, &new, &old).unwrap());
+    }
+}
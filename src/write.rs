@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//! Write new content to a temporary file next to a destination, so that it
+//! can be renamed over the destination atomically.
+//!
+//! The content is written to an unnamed `O_TMPFILE` in the directory of the
+//! destination, which is only linked to a hidden name after it is complete.
+//! Filesystems without `O_TMPFILE` support get a hidden file created directly.
+//! Either way, the file is on the same filesystem as the destination, so the
+//! final rename never crosses devices.
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::{fchown, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use rustix::fs::{self, AtFlags, Mode, OFlags};
+use rustix::io::Errno;
+
+use crate::names::{self, split};
+
+/// Write everything from `reader` to a new hidden file in the directory of
+/// `dest`, and return its path. The mode and the owner are copied from `dest`
+/// if it is an existing regular file. If `sync` is set, the content is
+/// fsync(2)ed before the file is named.
+///
+/// The owner is copied on a best-effort basis, since changing it usually
+/// requires privileges.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written. No file is left
+/// in this case.
+pub fn write_temp(reader: &mut impl Read, dest: &Path, sync: bool) -> io::Result<PathBuf> {
+    let (dir, _) = split(dest).ok_or(Errno::INVAL)?;
+    let default_mode = Mode::from_bits_truncate(0o666);
+    match fs::openat(
+        fs::CWD,
+        dir,
+        OFlags::TMPFILE | OFlags::WRONLY | OFlags::CLOEXEC,
+        default_mode,
+    ) {
+        Ok(fd) => {
+            let mut file = File::from(fd);
+            fill(&mut file, reader, dest, sync)?;
+            let fd_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+            let (temp, ()) = names::create_temp(dest, "write", |temp| {
+                fs::linkat(fs::CWD, &fd_path, fs::CWD, temp, AtFlags::SYMLINK_FOLLOW)
+            })?;
+            Ok(temp)
+        }
+        // Not supported by the filesystem or the kernel.
+        Err(Errno::OPNOTSUPP | Errno::ISDIR | Errno::INVAL) => {
+            let flags = OFlags::CREATE | OFlags::EXCL | OFlags::WRONLY | OFlags::CLOEXEC;
+            let (temp, fd) = names::create_temp(dest, "write", |temp| {
+                fs::openat(fs::CWD, temp, flags, default_mode)
+            })?;
+            fill(&mut File::from(fd), reader, dest, sync).inspect_err(|_| {
+                let _ = std::fs::remove_file(&temp);
+            })?;
+            Ok(temp)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Write the content and copy the metadata of `dest`.
+fn fill(file: &mut File, reader: &mut impl Read, dest: &Path, sync: bool) -> io::Result<()> {
+    io::copy(reader, file)?;
+    match dest.symlink_metadata() {
+        Ok(meta) if meta.is_file() => {
+            match fchown(&*file, Some(meta.uid()), Some(meta.gid())) {
+                Err(err) if err.raw_os_error() == Some(Errno::PERM.raw_os_error()) => {}
+                ret => ret?,
+            }
+            // After changing the owner, which clears setuid and setgid bits.
+            file.set_permissions(meta.permissions())?;
+        }
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::write_temp;
+    use crate::testutil::ScratchDir;
+
+    #[test]
+    fn test_write_temp() {
+        let dir = ScratchDir::new("write");
+        let dest = dir.join("dest");
+
+        let temp = write_temp(&mut &b"hello"[..], &dest, false).unwrap();
+        assert_eq!(temp.parent(), Some(&*dir));
+        assert_eq!(fs::read_to_string(&temp).unwrap(), "hello");
+
+        fs::write(&dest, "").unwrap();
+        fs::set_permissions(&dest, Permissions::from_mode(0o6750)).unwrap();
+        let temp2 = write_temp(&mut &b"world"[..], &dest, true).unwrap();
+        assert_ne!(temp, temp2);
+        assert_eq!(fs::read_to_string(&temp2).unwrap(), "world");
+        let mode = fs::metadata(&temp2).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o6750);
+
+        assert!(write_temp(&mut &b""[..], &dir.join("missing/dest"), false).is_err());
+    }
+}